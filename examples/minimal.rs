@@ -0,0 +1,30 @@
+//! what the bare feature set (`--no-default-features`) offers: create a FIFO and talk over the typed handles.
+//! Building this example is the check that the syscall layer stands on its own
+extern crate rust_pipe;
+
+use std::io::{Read, Write};
+use std::thread;
+
+use rust_pipe::{raw_mkfifo, NamedPipe, PipeReader, PipeWriter};
+
+fn main() {
+    let path = std::env::temp_dir().join(format!("rust_pipe_minimal_{}", std::process::id()));
+    let pipe = NamedPipe::new(&path);
+    pipe.validate().unwrap();
+    let c_path = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+    raw_mkfifo(&c_path, 0o600).unwrap();
+
+    let sender = {
+        let pipe = pipe.clone();
+        thread::spawn(move || {
+            let mut writer: PipeWriter = pipe.open_writer().unwrap();
+            writer.write_all(b"hello\n").unwrap();
+        })
+    };
+    let mut reader: PipeReader = pipe.open_reader().unwrap();
+    let mut buf = String::new();
+    reader.read_to_string(&mut buf).unwrap();
+    sender.join().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    print!("{}", buf);
+}
@@ -0,0 +1,265 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use core::writer::PipeWriter;
+
+/// what a queued subscriber does with a new message when its queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// wait until the subscriber's writer thread makes room, a stalled subscriber stalls `broadcast`
+    Block,
+    /// discard the message being broadcast
+    DropNewest,
+    /// discard the oldest queued message to make room
+    DropOldest,
+}
+
+/**
+send every framed message to all subscribed FIFOs
+
+by default `broadcast` writes to each subscriber in turn, so one slow reader slows everybody down.
+With `with_queue` each subscriber gets a bounded queue drained by its own thread and `broadcast` only enqueues
+*/
+#[derive(Debug)]
+pub struct Broadcaster {
+    queue: Option<(usize, OverflowPolicy)>,
+    subscribers: Vec<Subscriber>,
+}
+
+#[derive(Debug)]
+enum Subscriber {
+    Direct(PipeWriter),
+    Queued(Arc<Shared>),
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    messages: VecDeque<Vec<u8>>,
+    dropped: u64,
+    closed: bool,
+    /// writer thread gave up after this error
+    failed: Option<io::ErrorKind>,
+}
+
+impl Default for Broadcaster {
+    fn default() -> Self {
+        Broadcaster::new()
+    }
+}
+
+impl Broadcaster {
+    pub fn new() -> Self {
+        Broadcaster {
+            queue: None,
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// per subscriber queue of at most `capacity` messages, handled per `policy` when full
+    pub fn with_queue(capacity: usize, policy: OverflowPolicy) -> Self {
+        Broadcaster {
+            queue: Some((capacity.max(1), policy)),
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// returns the subscriber index used by `queued` and `dropped`
+    pub fn subscribe(&mut self, writer: PipeWriter) -> usize {
+        let subscriber = match self.queue {
+            None => Subscriber::Direct(writer),
+            Some(_) => {
+                let shared = Arc::new(Shared::default());
+                let thread_shared = shared.clone();
+                thread::spawn(move || drain_queue(&thread_shared, writer));
+                Subscriber::Queued(shared)
+            }
+        };
+        self.subscribers.push(subscriber);
+        self.subscribers.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+
+    /**
+    send `payload` as one framed message to every subscriber
+
+    every subscriber is tried, the first error is returned. A queued subscriber errors only after its writer thread failed
+    */
+    pub fn broadcast(&mut self, payload: &[u8]) -> io::Result<()> {
+        let mut first_err = None;
+        for subscriber in &mut self.subscribers {
+            let res = match *subscriber {
+                Subscriber::Direct(ref mut writer) => writer.write_message(payload),
+                Subscriber::Queued(ref shared) => {
+                    let (capacity, policy) = self.queue.expect("queued subscriber without queue config");
+                    enqueue(shared, payload, capacity, policy)
+                }
+            };
+            if let Err(e) = res {
+                first_err.get_or_insert(e);
+            }
+        }
+        match first_err {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// messages waiting in the subscriber's queue, always 0 without `with_queue`
+    pub fn queued(&self, index: usize) -> usize {
+        match self.subscribers[index] {
+            Subscriber::Direct(_) => 0,
+            Subscriber::Queued(ref shared) => shared.state.lock().unwrap().messages.len(),
+        }
+    }
+
+    /// messages discarded by the overflow policy for this subscriber
+    pub fn dropped(&self, index: usize) -> u64 {
+        match self.subscribers[index] {
+            Subscriber::Direct(_) => 0,
+            Subscriber::Queued(ref shared) => shared.state.lock().unwrap().dropped,
+        }
+    }
+}
+
+/// writer threads flush what is still queued and exit, they are not joined since a stalled reader would hang the drop
+impl Drop for Broadcaster {
+    fn drop(&mut self) {
+        for subscriber in &self.subscribers {
+            if let Subscriber::Queued(ref shared) = *subscriber {
+                shared.state.lock().unwrap().closed = true;
+                shared.changed.notify_all();
+            }
+        }
+    }
+}
+
+fn enqueue(shared: &Shared, payload: &[u8], capacity: usize, policy: OverflowPolicy) -> io::Result<()> {
+    let mut state = shared.state.lock().unwrap();
+    if let Some(kind) = state.failed {
+        return Err(io::Error::new(kind, "broadcast subscriber writer failed"));
+    }
+    if state.messages.len() >= capacity {
+        match policy {
+            OverflowPolicy::Block => {
+                while state.messages.len() >= capacity && state.failed.is_none() {
+                    state = shared.changed.wait(state).unwrap();
+                }
+                if let Some(kind) = state.failed {
+                    return Err(io::Error::new(kind, "broadcast subscriber writer failed"));
+                }
+            }
+            OverflowPolicy::DropNewest => {
+                state.dropped += 1;
+                return Ok(());
+            }
+            OverflowPolicy::DropOldest => {
+                state.messages.pop_front();
+                state.dropped += 1;
+            }
+        }
+    }
+    state.messages.push_back(payload.to_vec());
+    shared.changed.notify_all();
+    Ok(())
+}
+
+fn drain_queue(shared: &Shared, mut writer: PipeWriter) {
+    loop {
+        let message = {
+            let mut state = shared.state.lock().unwrap();
+            loop {
+                if let Some(message) = state.messages.pop_front() {
+                    break message;
+                }
+                if state.closed {
+                    return;
+                }
+                state = shared.changed.wait(state).unwrap();
+            }
+        };
+        // room in the queue for a blocked broadcast
+        shared.changed.notify_all();
+        if let Err(e) = writer.write_message(&message) {
+            let mut state = shared.state.lock().unwrap();
+            state.failed = Some(e.kind());
+            state.messages.clear();
+            shared.changed.notify_all();
+            return;
+        }
+    }
+}
+
+#[test]
+fn test_drop_oldest_with_stalled_subscriber() {
+    use std::time::Duration;
+
+    const CAPACITY: usize = 8;
+    const MESSAGES: u32 = 200;
+    let (fast_pipe, mut fast_reader, fast_writer) = ::test_pair("broadcast_fast");
+    let (stalled_pipe, stalled_reader, stalled_writer) = ::test_pair("broadcast_stalled");
+    let mut broadcaster = Broadcaster::with_queue(CAPACITY, OverflowPolicy::DropOldest);
+    let fast = broadcaster.subscribe(fast_writer);
+    let stalled = broadcaster.subscribe(stalled_writer);
+
+    let receiver = std::thread::spawn(move || {
+        let mut received = Vec::new();
+        while let Some(message) = fast_reader.read_message().unwrap() {
+            received.push(message);
+        }
+        received
+    });
+    let payload = [b'x'; 1024];
+    for i in 0..MESSAGES {
+        let mut message = i.to_be_bytes().to_vec();
+        message.extend_from_slice(&payload);
+        broadcaster.broadcast(&message).unwrap();
+        // the fast reader keeps up, so its queue never overflows
+        while broadcaster.queued(fast) > 0 {
+            std::thread::sleep(Duration::from_micros(100));
+        }
+        assert!(broadcaster.queued(stalled) <= CAPACITY);
+    }
+    assert_eq!(broadcaster.dropped(fast), 0);
+    assert!(broadcaster.dropped(stalled) > 0);
+    drop(broadcaster);
+
+    let received = receiver.join().unwrap();
+    assert_eq!(received.len(), MESSAGES as usize);
+    for (i, message) in received.iter().enumerate() {
+        assert_eq!(message[..4], (i as u32).to_be_bytes());
+    }
+    // unblocks the stalled writer thread with EPIPE
+    drop(stalled_reader);
+    std::fs::remove_file(fast_pipe.path()).unwrap();
+    std::fs::remove_file(stalled_pipe.path()).unwrap();
+}
+
+#[test]
+fn test_direct_broadcast() {
+    let (pipe_a, mut reader_a, writer_a) = ::test_pair("broadcast_direct_a");
+    let (pipe_b, mut reader_b, writer_b) = ::test_pair("broadcast_direct_b");
+    let mut broadcaster = Broadcaster::new();
+    broadcaster.subscribe(writer_a);
+    broadcaster.subscribe(writer_b);
+    broadcaster.broadcast(b"hello").unwrap();
+    drop(broadcaster);
+    assert_eq!(reader_a.read_message().unwrap().unwrap(), b"hello");
+    assert_eq!(reader_b.read_message().unwrap().unwrap(), b"hello");
+    std::fs::remove_file(pipe_a.path()).unwrap();
+    std::fs::remove_file(pipe_b.path()).unwrap();
+}
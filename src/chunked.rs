@@ -0,0 +1,185 @@
+/*!
+messages larger than `PIPE_BUF` from several producers at once
+
+a write of at most `PIPE_BUF` bytes to a FIFO is atomic, a bigger one can be interleaved with other producers at any byte.
+`ChunkedWriter` cuts each message into chunks which fit `chunk_size` (at most `PIPE_BUF`) including their framing,
+every chunk is one ordinary length-prefixed frame written with a single `write`. The frame body starts with
+
+| size | field                                           |
+|------|-------------------------------------------------|
+| 8    | producer id, big-endian, unique per writer      |
+| 1    | flags, `1` = more chunks of this message follow |
+
+`ChunkedReader` collects the chunks per producer id, so chunks of other producers in between don't matter.
+Without frames interleaved raw bytes can't be told apart again, which is why there is no plain `write` variant of this
+*/
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use libc;
+
+use core::reader::PipeReader;
+use core::writer::PipeWriter;
+use framing::{encode_header, HEADER_LEN};
+
+pub const CHUNK_HEADER_LEN: usize = 9;
+const FLAG_MORE: u8 = 1;
+
+static NEXT_PRODUCER: AtomicUsize = AtomicUsize::new(0);
+
+/// pid in the high half so writers of different processes on the same FIFO don't collide
+fn producer_id() -> u64 {
+    (u64::from(process::id()) << 32) | (NEXT_PRODUCER.fetch_add(1, Ordering::Relaxed) as u64 & 0xffff_ffff)
+}
+
+#[derive(Debug)]
+pub struct ChunkedWriter {
+    writer: PipeWriter,
+    chunk_size: usize,
+    producer: u64,
+}
+
+impl ChunkedWriter {
+    /// `chunk_size` is the whole write per chunk including frame and chunk header, `InvalidInput` above `PIPE_BUF`
+    pub fn new(writer: PipeWriter, chunk_size: usize) -> io::Result<Self> {
+        if chunk_size > libc::PIPE_BUF || chunk_size <= HEADER_LEN + CHUNK_HEADER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "chunk_size {} must be above {} and at most PIPE_BUF {}",
+                    chunk_size,
+                    HEADER_LEN + CHUNK_HEADER_LEN,
+                    libc::PIPE_BUF
+                ),
+            ));
+        }
+        Ok(ChunkedWriter {
+            writer,
+            chunk_size,
+            producer: producer_id(),
+        })
+    }
+
+    pub fn write_message(&mut self, payload: &[u8]) -> io::Result<()> {
+        let data_per_chunk = self.chunk_size - HEADER_LEN - CHUNK_HEADER_LEN;
+        let mut chunk = Vec::with_capacity(self.chunk_size);
+        let mut pieces = payload.chunks(data_per_chunk).peekable();
+        // an empty message is still one chunk
+        let mut first = true;
+        while first || pieces.peek().is_some() {
+            first = false;
+            let data = pieces.next().unwrap_or(&[]);
+            let flags = if pieces.peek().is_some() { FLAG_MORE } else { 0 };
+            chunk.clear();
+            chunk.extend_from_slice(&encode_header(CHUNK_HEADER_LEN + data.len())?);
+            chunk.extend_from_slice(&self.producer.to_be_bytes());
+            chunk.push(flags);
+            chunk.extend_from_slice(data);
+            // at most PIPE_BUF, so the kernel writes it whole or not at all
+            self.writer.write_all(&chunk)?;
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> PipeWriter {
+        self.writer
+    }
+}
+
+/// reassembles `ChunkedWriter` messages, keyed by producer, in the order they complete
+#[derive(Debug)]
+pub struct ChunkedReader {
+    reader: PipeReader,
+    partial: HashMap<u64, Vec<u8>>,
+}
+
+impl ChunkedReader {
+    pub fn new(reader: PipeReader) -> Self {
+        ChunkedReader {
+            reader,
+            partial: HashMap::new(),
+        }
+    }
+
+    /// next complete message, `Ok(None)` at EOF, `UnexpectedEof` if a producer left a message unfinished
+    pub fn read_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let body = match self.reader.read_message()? {
+                Some(body) => body,
+                None if self.partial.is_empty() => return Ok(None),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("pipe closed with {} unfinished chunked message(s)", self.partial.len()),
+                    ))
+                }
+            };
+            if body.len() < CHUNK_HEADER_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("frame of {} bytes is too short for a chunk header", body.len()),
+                ));
+            }
+            let mut producer = [0u8; 8];
+            producer.copy_from_slice(&body[..8]);
+            let producer = u64::from_be_bytes(producer);
+            let more = body[8] & FLAG_MORE != 0;
+            let data = &body[CHUNK_HEADER_LEN..];
+            if more {
+                self.partial.entry(producer).or_default().extend_from_slice(data);
+                continue;
+            }
+            let mut message = self.partial.remove(&producer).unwrap_or_default();
+            message.extend_from_slice(data);
+            return Ok(Some(message));
+        }
+    }
+
+    pub fn into_inner(self) -> PipeReader {
+        self.reader
+    }
+}
+
+#[test]
+fn test_concurrent_producers_reassemble() {
+    const PRODUCERS: u8 = 4;
+    const MESSAGES: usize = 20;
+    const MESSAGE_LEN: usize = 20_000;
+
+    let (pipe, reader, first_writer) = ::test_pair("chunked_producers");
+    let mut writers = vec![first_writer];
+    for _ in 1..PRODUCERS {
+        writers.push(pipe.open_writer().unwrap());
+    }
+    let producers: Vec<_> = writers
+        .into_iter()
+        .enumerate()
+        .map(|(id, writer)| {
+            std::thread::spawn(move || {
+                let mut writer = ChunkedWriter::new(writer, libc::PIPE_BUF).unwrap();
+                for i in 0..MESSAGES {
+                    let mut message = vec![id as u8; MESSAGE_LEN];
+                    message[0] = i as u8;
+                    writer.write_message(&message).unwrap();
+                }
+            })
+        })
+        .collect();
+
+    let mut reader = ChunkedReader::new(reader);
+    let mut next = [0usize; PRODUCERS as usize];
+    while let Some(message) = reader.read_message().unwrap() {
+        assert_eq!(message.len(), MESSAGE_LEN);
+        let id = message[1] as usize;
+        assert!(message[1..].iter().all(|&b| b as usize == id), "message mixes producers");
+        assert_eq!(message[0] as usize, next[id]);
+        next[id] += 1;
+    }
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    assert_eq!(next, [MESSAGES; PRODUCERS as usize]);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
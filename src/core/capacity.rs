@@ -0,0 +1,147 @@
+/*!
+pipe buffer size via `F_GETPIPE_SZ`/`F_SETPIPE_SZ` (Linux only)
+
+the buffer belongs to the pipe while it's open and is shared by every fd on it, but once all ends of a FIFO are closed
+it's freed and the next open starts again at the default (usually 64 KiB), so a size set before a reconnect is gone after it
+*/
+use std::fs;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use libc;
+
+use core::sys::cvt;
+use core::writer::PipeWriter;
+
+/// what `PipeWriter::request_capacity` got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityGrant {
+    /// the buffer size now in effect
+    pub size: usize,
+    /// `EPERM` made it fall back to `/proc/sys/fs/pipe-max-size`
+    pub clamped: bool,
+}
+
+impl PipeWriter {
+    /// current size of the pipe buffer in bytes
+    pub fn capacity(&self) -> io::Result<usize> {
+        Ok(cvt(unsafe { libc::fcntl(self.as_raw_fd(), libc::F_GETPIPE_SZ) })? as usize)
+    }
+
+    /**
+    resize the pipe buffer, the kernel rounds up to a power-of-two number of pages and returns what it picked
+
+    an unprivileged process asking for more than `/proc/sys/fs/pipe-max-size` gets `EPERM`, the request is then clamped
+    to that limit and retried once, so the returned size can be smaller than `size`. See [`set_strict_capacity`] to fail
+    instead and [`request_capacity`] to find out whether it happened
+
+    [`set_strict_capacity`]: #method.set_strict_capacity
+    [`request_capacity`]: #method.request_capacity
+    */
+    pub fn set_capacity(&self, size: usize) -> io::Result<usize> {
+        Ok(self.request_capacity(size)?.size)
+    }
+
+    /**
+    `set_capacity` which also says whether the request was clamped to `pipe-max-size`
+
+    comparing the returned size with the requested one can't tell, the kernel rounds every size up
+    */
+    pub fn request_capacity(&self, size: usize) -> io::Result<CapacityGrant> {
+        match self.fcntl_set_capacity(size) {
+            Err(ref e) if e.raw_os_error() == Some(libc::EPERM) && !self.strict_capacity => {
+                let max = pipe_max_size()?;
+                if max >= size {
+                    // EPERM wasn't about the size limit, e.g. the per user pipe pages quota
+                    return Err(io::Error::from_raw_os_error(libc::EPERM));
+                }
+                Ok(CapacityGrant { size: self.fcntl_set_capacity(max)?, clamped: true })
+            }
+            res => Ok(CapacityGrant { size: res?, clamped: false }),
+        }
+    }
+
+    /// make [`set_capacity`](#method.set_capacity)/`ensure_capacity` return `EPERM` instead of clamping to the system limit
+    pub fn set_strict_capacity(&mut self, strict: bool) {
+        self.strict_capacity = strict;
+    }
+
+    fn fcntl_set_capacity(&self, size: usize) -> io::Result<usize> {
+        let size = size.min(libc::c_int::MAX as usize) as libc::c_int;
+        Ok(cvt(unsafe { libc::fcntl(self.as_raw_fd(), libc::F_SETPIPE_SZ, size) })? as usize)
+    }
+
+    /**
+    grow the buffer to at least `min` bytes, never shrink it, returns the effective size
+
+    cheap enough to call on every freshly (re)opened writer, only does `F_SETPIPE_SZ` if the buffer is too small
+    */
+    pub fn ensure_capacity(&self, min: usize) -> io::Result<usize> {
+        let current = self.capacity()?;
+        if current >= min {
+            return Ok(current);
+        }
+        self.set_capacity(min)
+    }
+}
+
+/// largest buffer an unprivileged process may ask for
+pub(crate) fn pipe_max_size() -> io::Result<usize> {
+    let max = fs::read_to_string("/proc/sys/fs/pipe-max-size")?;
+    max.trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("pipe-max-size {:?}: {}", max.trim(), e)))
+}
+
+#[test]
+fn test_ensure_capacity_per_open() {
+    const FLOOR: usize = 128 * 1024;
+    let pipe = ::test_pipe("ensure_capacity");
+    for _ in 0..2 {
+        let reader = {
+            let pipe = pipe.clone();
+            std::thread::spawn(move || pipe.open_reader().unwrap())
+        };
+        let writer = pipe.open_writer().unwrap();
+        let reader = reader.join().unwrap();
+        // every open starts over at the default size
+        assert!(writer.capacity().unwrap() < FLOOR);
+        assert!(writer.ensure_capacity(FLOOR).unwrap() >= FLOOR);
+        assert!(writer.capacity().unwrap() >= FLOOR);
+        // asking for less leaves it alone
+        assert!(writer.ensure_capacity(4096).unwrap() >= FLOOR);
+        assert!(!writer.request_capacity(FLOOR).unwrap().clamped);
+        drop((reader, writer));
+    }
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_set_capacity_clamps_to_pipe_max_size() {
+    let max = pipe_max_size().unwrap();
+    let pid = unsafe { libc::fork() };
+    assert_ne!(pid, -1);
+    if pid == 0 {
+        // root may go past pipe-max-size, so the child gives up its privileges first
+        if unsafe { libc::geteuid() } == 0 && unsafe { libc::setuid(65534) } != 0 {
+            unsafe { libc::_exit(2) };
+        }
+        let (_reader, mut writer) = ::Pipe::anonymous().unwrap();
+        let code = match writer.ensure_capacity(max * 64) {
+            Ok(size) if size == max && writer.request_capacity(max * 64).ok().is_some_and(|grant| grant.clamped) => {
+                writer.set_strict_capacity(true);
+                match writer.set_capacity(max * 64) {
+                    Err(ref e) if e.raw_os_error() == Some(libc::EPERM) => 0,
+                    _ => 4,
+                }
+            }
+            Ok(_) => 3,
+            Err(_) => 1,
+        };
+        unsafe { libc::_exit(code) };
+    }
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status));
+    assert_eq!(libc::WEXITSTATUS(status), 0);
+}
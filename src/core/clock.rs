@@ -0,0 +1,198 @@
+/*!
+time source of the timeout based methods, swappable so their tests don't have to sleep for real
+
+the `*_with_clock` variants take a `&dyn Clock`, the plain ones use `SystemClock`
+*/
+use std::io;
+use std::os::unix::io::RawFd;
+#[cfg(feature = "clock")]
+use std::sync::Mutex;
+#[cfg(any(feature = "clock", feature = "retry"))]
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc;
+
+#[cfg(feature = "retry")]
+use core::spin::SpinPolicy;
+use core::sys::poll_fd;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// only waits of `RetryPolicy` sleep
+    #[cfg(any(feature = "clock", feature = "retry"))]
+    fn sleep(&self, duration: Duration);
+
+    /**
+    let `duration` pass waiting the way `spin` says, e.g. a retry backoff
+
+    one `sleep` of the whole `duration` under `Sleep`, `Busy` and `Yield` pause until `now` is past it
+    */
+    #[cfg(feature = "retry")]
+    fn spin_wait(&self, duration: Duration, spin: SpinPolicy) {
+        if let SpinPolicy::Sleep(_) = spin {
+            return self.sleep(duration);
+        }
+        let deadline = self.now() + duration;
+        while self.now() < deadline {
+            spin.pause();
+        }
+    }
+
+    /// poll one fd for `events`, returns its revents or 0 once `timeout` passed, `None` waits forever
+    fn poll_fd(&self, fd: RawFd, events: libc::c_short, timeout: Option<Duration>) -> io::Result<libc::c_short> {
+        poll_fd(fd, events, timeout)
+    }
+}
+
+/// the real monotonic clock, `Instant::now` and `thread::sleep`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    #[cfg(any(feature = "clock", feature = "retry"))]
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/**
+clock for tests which only moves when told to or when something waits on it
+
+`sleep` and a `poll_fd` finding the fd not ready return right away and advance the clock by the full duration,
+so a timeout fires exactly at its deadline without real waiting. `poll_fd` without a timeout still blocks for real
+*/
+#[cfg(feature = "clock")]
+#[derive(Debug)]
+pub struct MockClock {
+    start: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+#[cfg(feature = "clock")]
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+#[cfg(feature = "clock")]
+impl MockClock {
+    pub fn new() -> Self {
+        MockClock {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::from_secs(0)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    /// mock time passed since `new`
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+#[cfg(feature = "clock")]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration)
+    }
+
+    /// nothing would move the clock while spinning, the whole `duration` passes at once whatever `spin` is
+    #[cfg(feature = "retry")]
+    fn spin_wait(&self, duration: Duration, _spin: SpinPolicy) {
+        self.advance(duration)
+    }
+
+    fn poll_fd(&self, fd: RawFd, events: libc::c_short, timeout: Option<Duration>) -> io::Result<libc::c_short> {
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return poll_fd(fd, events, None),
+        };
+        let revents = poll_fd(fd, events, Some(Duration::from_secs(0)))?;
+        if revents == 0 {
+            self.advance(timeout);
+        }
+        Ok(revents)
+    }
+}
+
+#[cfg(feature = "clock")]
+#[test]
+fn test_read_timeout_fires_at_mock_deadline() {
+    let (pipe, mut reader, _writer) = ::test_pair("mock_clock_read");
+    let clock = MockClock::new();
+    let real_start = Instant::now();
+    let err = reader
+        .read_timeout_with_clock(&mut [0u8; 16], Duration::from_secs(30), &clock)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(clock.elapsed(), Duration::from_secs(30));
+    assert!(real_start.elapsed() < Duration::from_secs(5));
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[cfg(all(feature = "clock", feature = "retry"))]
+#[test]
+fn test_connect_with_retry_sleeps_on_mock_clock() {
+    use core::retry::RetryPolicy;
+
+    let pipe = ::test_pipe("mock_clock_connect");
+    let policy = RetryPolicy {
+        max_attempts: 4,
+        initial_backoff: Duration::from_secs(10),
+        max_backoff: Duration::from_secs(25),
+        multiplier: 2.0,
+        ..RetryPolicy::default()
+    };
+    let clock = MockClock::new();
+    let err = pipe.connect_with_retry_with_clock(&policy, &clock).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENXIO));
+    // 10s + 20s + 25s between the four attempts
+    assert_eq!(clock.elapsed(), Duration::from_secs(55));
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[cfg(all(feature = "clock", feature = "retry"))]
+#[test]
+fn test_busy_retry_advances_mock_clock() {
+    use core::retry::RetryPolicy;
+    use core::spin::SpinPolicy;
+
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_secs(60),
+        max_backoff: Duration::from_secs(60),
+        multiplier: 1.0,
+        spin: SpinPolicy::Busy,
+    };
+    let clock = MockClock::new();
+    let real_start = Instant::now();
+    let mut attempts = 0;
+    let err = policy
+        .retry_with_clock(
+            || -> io::Result<()> {
+                attempts += 1;
+                Err(io::Error::from_raw_os_error(libc::ENXIO))
+            },
+            |_| true,
+            &clock,
+        )
+        .unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENXIO));
+    assert_eq!(attempts, 3);
+    assert_eq!(clock.elapsed(), Duration::from_secs(120));
+    assert!(real_start.elapsed() < Duration::from_secs(5));
+}
@@ -0,0 +1,214 @@
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use libc;
+
+use core::pipe::NamedPipe;
+use core::reader::PipeReader;
+use core::sys::cvt;
+use core::writer::PipeWriter;
+
+const SCHEME: &str = "fifo://";
+
+/**
+every option of a FIFO in one place, parseable from a string for env vars and CLI args:
+
+```text
+fifo:///tmp/foo?mode=0660&nonblock=true&capacity=65536&cloexec=false
+```
+
+only the path is required, it has to be absolute and is taken verbatim (no percent-decoding).
+Defaults: `mode=0600`, `nonblock=false`, no capacity change, `cloexec=true`
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamedPipeConfig {
+    pub path: PathBuf,
+    /// permission bits for mkfifo, before the umask
+    pub mode: u32,
+    /// open both ends with `O_NONBLOCK` and leave them non-blocking
+    pub nonblock: bool,
+    /// pipe buffer size the writer asks for (Linux only), see `PipeWriter::ensure_capacity`
+    pub capacity: Option<usize>,
+    pub cloexec: bool,
+}
+
+impl NamedPipeConfig {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        NamedPipeConfig {
+            path: path.into(),
+            mode: 0o600,
+            nonblock: false,
+            capacity: None,
+            cloexec: true,
+        }
+    }
+
+    fn open_options(&self) -> OpenOptions {
+        let mut options = OpenOptions::new();
+        if self.nonblock {
+            options.custom_flags(libc::O_NONBLOCK);
+        }
+        options
+    }
+
+    fn apply_cloexec(&self, fd: libc::c_int) -> io::Result<()> {
+        // std opens with O_CLOEXEC, only turning it off needs a syscall
+        if !self.cloexec {
+            cvt(unsafe { libc::fcntl(fd, libc::F_SETFD, 0) })?;
+        }
+        Ok(())
+    }
+
+    pub fn open_reader(&self) -> io::Result<PipeReader> {
+        let file = self.open_options().read(true).open(&self.path)?;
+        self.apply_cloexec(file.as_raw_fd())?;
+        Ok(PipeReader::from_file(file))
+    }
+
+    /// with `nonblock` this fails with `ENXIO` if no reader is there yet, like `try_open_writer`
+    pub fn open_writer(&self) -> io::Result<PipeWriter> {
+        let file = self.open_options().write(true).open(&self.path)?;
+        self.apply_cloexec(file.as_raw_fd())?;
+        let writer = PipeWriter::from_file(file).with_origin(&NamedPipe::new(&self.path));
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(capacity) = self.capacity {
+                writer.ensure_capacity(capacity)?;
+            }
+        }
+        Ok(writer)
+    }
+}
+
+fn invalid(config: &str, why: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("pipe config {:?}: {}", config, why))
+}
+
+fn parse_bool(config: &str, key: &str, value: &str) -> io::Result<bool> {
+    match value {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(invalid(config, format!("{} must be true or false, not {:?}", key, value))),
+    }
+}
+
+impl FromStr for NamedPipeConfig {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        if !s.starts_with(SCHEME) {
+            return Err(invalid(s, format!("doesn't start with {}", SCHEME)));
+        }
+        let rest = &s[SCHEME.len()..];
+        let (path, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+        if !path.starts_with('/') {
+            return Err(invalid(s, "the path must be absolute, e.g. fifo:///tmp/pipe".to_string()));
+        }
+        let mut config = NamedPipeConfig::new(path);
+        for pair in query.into_iter().flat_map(|query| query.split('&')).filter(|pair| !pair.is_empty()) {
+            let (key, value) = match pair.find('=') {
+                Some(i) => (&pair[..i], &pair[i + 1..]),
+                None => return Err(invalid(s, format!("{:?} is not key=value", pair))),
+            };
+            match key {
+                "mode" => {
+                    let digits = value.trim_start_matches("0o");
+                    config.mode = match u32::from_str_radix(digits, 8) {
+                        Ok(mode) if mode <= 0o7777 => mode,
+                        _ => return Err(invalid(s, format!("mode must be octal permission bits, not {:?}", value))),
+                    };
+                }
+                "nonblock" => config.nonblock = parse_bool(s, key, value)?,
+                "cloexec" => config.cloexec = parse_bool(s, key, value)?,
+                "capacity" => {
+                    config.capacity = match value.parse() {
+                        Ok(capacity) => Some(capacity),
+                        Err(_) => return Err(invalid(s, format!("capacity must be a byte count, not {:?}", value))),
+                    };
+                }
+                _ => return Err(invalid(s, format!("unknown option {:?}", key))),
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl NamedPipe {
+    /**
+    mkfifo `config.path` with `config.mode` (an existing FIFO is fine), nothing is opened
+
+    `mode` is the only option a FIFO on disk has, `nonblock`, `capacity` and `cloexec` belong to open fds and are
+    applied by `config.open_reader()`/`config.open_writer()`, not by `NamedPipe::open_*`
+    */
+    pub fn create_from_config(config: &NamedPipeConfig) -> io::Result<NamedPipe> {
+        let pipe = NamedPipe::new(&config.path);
+        pipe.create(config.mode)?;
+        Ok(pipe)
+    }
+}
+
+#[test]
+fn test_parse_full_config() {
+    let config: NamedPipeConfig = "fifo:///tmp/foo?mode=0660&nonblock=true&capacity=65536&cloexec=false"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        config,
+        NamedPipeConfig {
+            path: PathBuf::from("/tmp/foo"),
+            mode: 0o660,
+            nonblock: true,
+            capacity: Some(65536),
+            cloexec: false,
+        }
+    );
+}
+
+#[test]
+fn test_parse_minimal_config() {
+    let config: NamedPipeConfig = "fifo:///run/app/pipe".parse().unwrap();
+    assert_eq!(config, NamedPipeConfig::new("/run/app/pipe"));
+}
+
+#[test]
+fn test_parse_config_errors() {
+    for bad in &[
+        "fifo:///tmp/foo?mode=0999",
+        "fifo:///tmp/foo?mode=77777",
+        "fifo:///tmp/foo?color=red",
+        "fifo:///tmp/foo?nonblock=yes",
+        "fifo://tmp/foo",
+        "/tmp/foo",
+    ] {
+        let err = bad.parse::<NamedPipeConfig>().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", bad);
+    }
+    let err = "fifo:///tmp/foo?color=red".parse::<NamedPipeConfig>().unwrap_err();
+    assert!(err.to_string().contains("unknown option \"color\""), "{}", err);
+}
+
+#[test]
+fn test_open_from_config() {
+    let path = ::test_pipe("from_config").path().to_path_buf();
+    let config: NamedPipeConfig = format!("fifo://{}?nonblock=true&cloexec=false&capacity=262144", path.display())
+        .parse()
+        .unwrap();
+    let pipe = NamedPipe::create_from_config(&config).unwrap();
+    let mut reader = config.open_reader().unwrap();
+    let writer = config.open_writer().unwrap();
+    assert_eq!(unsafe { libc::fcntl(reader.as_raw_fd(), libc::F_GETFD) } & libc::FD_CLOEXEC, 0);
+    assert_ne!(unsafe { libc::fcntl(writer.as_raw_fd(), libc::F_GETFL) } & libc::O_NONBLOCK, 0);
+    // nonblock reached the reader: nothing written yet is WouldBlock instead of a hang
+    let err = io::Read::read(&mut reader, &mut [0u8; 1]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    #[cfg(target_os = "linux")]
+    assert!(writer.capacity().unwrap() >= 262_144);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
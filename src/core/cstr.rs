@@ -0,0 +1,76 @@
+/*!
+NUL-terminated strings as C programs write them, like `sender_process` with its trailing `\0`
+
+every string is its UTF-8 bytes and one `\0`, what C code doing `write(fd, s, strlen(s) + 1)` sends
+*/
+use std::io::{self, IoSlice};
+
+use core::reader::BufPipeReader;
+use core::writer::PipeWriter;
+
+impl PipeWriter {
+    /// `s` and its `\0` in one write, `InvalidInput` if `s` has a NUL of its own which would cut it short for the reader
+    pub fn write_cstr(&mut self, s: &str) -> io::Result<()> {
+        if let Some(offset) = s.bytes().position(|b| b == 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("string contains a nul byte at offset {}", offset),
+            ));
+        }
+        self.write_all_vectored(&mut [IoSlice::new(s.as_bytes()), IoSlice::new(b"\0")])
+    }
+}
+
+impl BufPipeReader {
+    /// next string up to its `\0`, which is stripped, `Ok(None)` at EOF and `UnexpectedEof` for a string without one
+    pub fn read_cstr(&mut self) -> io::Result<Option<String>> {
+        let mut buf = Vec::new();
+        if self.read_until(b'\0', &mut buf)? == 0 {
+            return Ok(None);
+        }
+        if buf.pop() != Some(b'\0') {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("pipe closed inside a string after {} bytes", buf.len() + 1),
+            ));
+        }
+        String::from_utf8(buf)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[test]
+fn test_cstr_roundtrip() {
+    let (pipe, reader, mut writer) = ::test_pair("cstr_roundtrip");
+    let mut reader = reader.buffered();
+    writer.write_cstr("hello").unwrap();
+    writer.write_cstr("").unwrap();
+    writer.write_cstr("grüße\n").unwrap();
+    assert_eq!(writer.write_cstr("a\0b").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    drop(writer);
+    assert_eq!(reader.read_cstr().unwrap().unwrap(), "hello");
+    assert_eq!(reader.read_cstr().unwrap().unwrap(), "");
+    assert_eq!(reader.read_cstr().unwrap().unwrap(), "grüße\n");
+    assert_eq!(reader.read_cstr().unwrap(), None);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_read_cstr_from_raw_writer() {
+    use std::io::Write;
+
+    let pipe = ::test_pipe("cstr_raw");
+    let reader = {
+        let pipe = pipe.clone();
+        std::thread::spawn(move || pipe.open_reader().unwrap())
+    };
+    // what a C program does: write(fd, "hello", strlen("hello") + 1)
+    let mut raw = std::fs::OpenOptions::new().write(true).open(pipe.path()).unwrap();
+    let mut reader = reader.join().unwrap().buffered();
+    raw.write_all(b"hello\0unterminated").unwrap();
+    drop(raw);
+    assert_eq!(reader.read_cstr().unwrap().unwrap(), "hello");
+    assert_eq!(reader.read_cstr().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
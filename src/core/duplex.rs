@@ -0,0 +1,123 @@
+use std::io::{self, Read, Write};
+
+use core::pipe::NamedPipe;
+use core::reader::PipeReader;
+use core::writer::PipeWriter;
+
+/**
+two way channel built from two FIFOs, one per direction
+
+each blocking open waits for its peer, so the two sides must open in opposite order or both hang:
+one side calls `connect` (write end first) and the other `accept` (read end first)
+*/
+#[derive(Debug)]
+pub struct Duplex {
+    reader: PipeReader,
+    writer: Option<PipeWriter>,
+}
+
+impl Duplex {
+    pub fn from_parts(reader: PipeReader, writer: PipeWriter) -> Self {
+        Duplex {
+            reader,
+            writer: Some(writer),
+        }
+    }
+
+    /// open `tx` for writing then `rx` for reading, the peer must `accept` with the same two FIFOs
+    pub fn connect(tx: &NamedPipe, rx: &NamedPipe) -> io::Result<Self> {
+        let writer = tx.open_writer()?;
+        let reader = rx.open_reader()?;
+        Ok(Duplex::from_parts(reader, writer))
+    }
+
+    /// open `rx` for reading then `tx` for writing, pairs with the peer's `connect`
+    pub fn accept(tx: &NamedPipe, rx: &NamedPipe) -> io::Result<Self> {
+        let reader = rx.open_reader()?;
+        let writer = tx.open_writer()?;
+        Ok(Duplex::from_parts(reader, writer))
+    }
+
+    /**
+    close the sending direction so the peer's reads get EOF, while this side can still read replies
+
+    FIFOs have no real half-close like TCP `shutdown(SHUT_WR)`, this just closes our write fd,
+    so the peer only sees EOF if no other process holds that FIFO open for writing. Later writes fail with `BrokenPipe`
+    */
+    pub fn shutdown_write(&mut self) {
+        self.writer = None;
+    }
+
+    #[cfg(feature = "framing")]
+    pub(crate) fn reader_mut(&mut self) -> &mut PipeReader {
+        &mut self.reader
+    }
+
+    pub(crate) fn writer(&mut self) -> io::Result<&mut PipeWriter> {
+        match self.writer {
+            Some(ref mut writer) => Ok(writer),
+            None => Err(io::Error::new(io::ErrorKind::BrokenPipe, "write side of duplex is shut down")),
+        }
+    }
+
+    /// `write_message` on the sending direction
+    #[cfg(feature = "framing")]
+    pub fn send(&mut self, payload: &[u8]) -> io::Result<()> {
+        self.writer()?.write_message(payload)
+    }
+
+    /// next frame from the peer, `Ok(None)` at EOF
+    #[cfg(feature = "framing")]
+    pub fn read_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.reader.read_message()
+    }
+}
+
+impl Read for Duplex {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for Duplex {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer()?.flush()
+    }
+}
+
+/// both ends of a duplex over two fresh FIFOs
+#[cfg(test)]
+pub(crate) fn test_duplex(tag: &str) -> (NamedPipe, NamedPipe, Duplex, Duplex) {
+    let a_to_b = ::test_pipe(&format!("{}_a_to_b", tag));
+    let b_to_a = ::test_pipe(&format!("{}_b_to_a", tag));
+    let peer = {
+        let (a_to_b, b_to_a) = (a_to_b.clone(), b_to_a.clone());
+        std::thread::spawn(move || Duplex::accept(&b_to_a, &a_to_b).unwrap())
+    };
+    let a = Duplex::connect(&a_to_b, &b_to_a).unwrap();
+    (a_to_b, b_to_a, a, peer.join().unwrap())
+}
+
+#[test]
+fn test_shutdown_write_half_close() {
+    let (a_to_b, b_to_a, mut a, mut b) = test_duplex("shutdown_write");
+    a.write_all(b"request").unwrap();
+    a.shutdown_write();
+    assert_eq!(a.write(b"more").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+
+    let mut request = Vec::new();
+    b.read_to_end(&mut request).unwrap();
+    assert_eq!(request, b"request");
+    b.write_all(b"response").unwrap();
+    drop(b);
+
+    let mut response = Vec::new();
+    a.read_to_end(&mut response).unwrap();
+    assert_eq!(response, b"response");
+    std::fs::remove_file(a_to_b.path()).unwrap();
+    std::fs::remove_file(b_to_a.path()).unwrap();
+}
@@ -0,0 +1,161 @@
+//! errno access and formatting
+use std::io;
+
+use libc;
+
+#[cfg(any(target_os = "freebsd",
+                 target_os = "ios",
+                 target_os = "macos"))] 
+        pub(crate) unsafe fn errno_location() -> *mut libc::c_int {
+            libc::__error()
+        }
+ #[cfg(any(target_os = "android",
+                        target_os = "netbsd",
+                        target_os = "openbsd"))] 
+        pub(crate) unsafe fn errno_location() -> *mut libc::c_int {
+            libc::__errno()
+        }
+ #[cfg(target_os = "linux")] 
+        pub(crate) unsafe fn errno_location() -> *mut libc::c_int {
+            libc::__errno_location()
+        }
+#[cfg(any(target_os = "illumos", target_os = "solaris"))] 
+        pub(crate) unsafe fn errno_location() -> *mut libc::c_int {
+            libc::___errno()
+        }
+#[cfg(not(any(target_os = "freebsd", target_os = "ios", target_os = "macos", target_os = "android",
+              target_os = "netbsd", target_os = "openbsd", target_os = "linux", target_os = "illumos",
+              target_os = "solaris")))]
+compile_error!("errno_location: add this target's errno accessor from libc (see std's sys/unix/os.rs) to the list above");
+
+pub(crate) fn errno_to_err_msg(errno: i32) -> String {
+    let err_msg_buf  = [0u8; 128];
+    unsafe { libc::strerror_r(errno, err_msg_buf.as_ptr() as _,128) };
+    let err_msg_buf_len = err_msg_buf.iter().position(|&x| x == b'\0').unwrap();
+    // one allocation, String::from_utf8_unchecked(buf.to_vec()) had the same cost but skipped the utf8 check
+    String::from_utf8_lossy(&err_msg_buf[..err_msg_buf_len]).into_owned()
+}
+
+/**
+errno as an `io::Error` which keeps `raw_os_error`, never allocates
+
+the strerror_r text is only looked up if the error is ever displayed, so a non-blocking loop hitting `EAGAIN`
+all the time pays nothing for it. `errno_to_err_msg` builds the message up front where a `String` is wanted
+*/
+pub fn errno_to_error(errno: i32) -> io::Error {
+    io::Error::from_raw_os_error(errno)
+}
+
+/**
+errno of the last failed syscall on this thread, 0 if there is none
+
+goes through std, which knows the errno accessor of every target, `errno_location` is only for writing errno
+*/
+pub(crate) fn last_errno() -> i32 {
+    io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
+
+/**
+stable symbolic name of an errno, e.g. `"ENOENT"`
+
+unlike strerror_r the result doesn't depend on the locale, so it's safe to put into logs which get parsed
+*/
+pub fn errno_name(errno: i32) -> &'static str {
+    match errno {
+        0 => "OK",
+        libc::EPERM => "EPERM",
+        libc::ENOENT => "ENOENT",
+        libc::ESRCH => "ESRCH",
+        libc::EINTR => "EINTR",
+        libc::EIO => "EIO",
+        libc::ENXIO => "ENXIO",
+        libc::E2BIG => "E2BIG",
+        libc::ENOEXEC => "ENOEXEC",
+        libc::EBADF => "EBADF",
+        libc::ECHILD => "ECHILD",
+        libc::EAGAIN => "EAGAIN",
+        libc::ENOMEM => "ENOMEM",
+        libc::EACCES => "EACCES",
+        libc::EFAULT => "EFAULT",
+        libc::EBUSY => "EBUSY",
+        libc::EEXIST => "EEXIST",
+        libc::EXDEV => "EXDEV",
+        libc::ENODEV => "ENODEV",
+        libc::ENOTDIR => "ENOTDIR",
+        libc::EISDIR => "EISDIR",
+        libc::EINVAL => "EINVAL",
+        libc::ENFILE => "ENFILE",
+        libc::EMFILE => "EMFILE",
+        libc::ENOTTY => "ENOTTY",
+        libc::ETXTBSY => "ETXTBSY",
+        libc::EFBIG => "EFBIG",
+        libc::ENOSPC => "ENOSPC",
+        libc::ESPIPE => "ESPIPE",
+        libc::EROFS => "EROFS",
+        libc::EMLINK => "EMLINK",
+        libc::EPIPE => "EPIPE",
+        libc::EDOM => "EDOM",
+        libc::ERANGE => "ERANGE",
+        libc::EDEADLK => "EDEADLK",
+        libc::ENAMETOOLONG => "ENAMETOOLONG",
+        libc::ENOSYS => "ENOSYS",
+        libc::ENOTEMPTY => "ENOTEMPTY",
+        libc::ELOOP => "ELOOP",
+        libc::ENOTSUP => "ENOTSUP",
+        libc::ETIMEDOUT => "ETIMEDOUT",
+        _ => "UNKNOWN",
+    }
+}
+
+#[test]
+fn test_errno_name() {
+    // a pure match over the libc constants, LC_MESSAGES or setlocale can't change it
+    assert_eq!(errno_name(libc::ENOENT), "ENOENT");
+    assert_eq!(errno_name(libc::EACCES), "EACCES");
+    assert_eq!(errno_name(libc::EWOULDBLOCK), "EAGAIN");
+    assert_eq!(errno_name(-1), "UNKNOWN");
+}
+
+#[test]
+fn test_last_errno_after_failed_syscall() {
+    let fd = unsafe { libc::open("/tmp/not_exist_file\0".as_ptr() as _, libc::O_RDONLY) };
+    assert_eq!(fd, -1);
+    assert_eq!(last_errno(), libc::ENOENT);
+    assert_eq!(unsafe { libc::close(-1) }, -1);
+    assert_eq!(last_errno(), libc::EBADF);
+    // both accessors see the same thread local
+    assert_eq!(unsafe { *errno_location() }, libc::EBADF);
+}
+
+#[test]
+fn test_errno_no_such_file_or_directory() {
+    let fd = unsafe { libc::open("/tmp/not_exist_file\0".as_ptr() as _, libc::O_RDONLY) };
+    let errno = unsafe { *errno_location() };
+    dbg!(fd, errno_to_err_msg(errno));
+}
+
+#[test]
+fn test_errno_to_error_does_not_allocate() {
+    let before = ::alloc_count::allocations();
+    assert_eq!(errno_to_error(libc::EAGAIN).kind(), io::ErrorKind::WouldBlock);
+    assert_eq!(errno_to_error(libc::EINTR).kind(), io::ErrorKind::Interrupted);
+    let err = errno_to_error(libc::ENOENT);
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+    // the path every failed syscall of this crate takes
+    let err = ::core::sys::cvt(unsafe { libc::close(-1) }).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+    assert_eq!(::alloc_count::allocations(), before);
+}
+
+#[cfg(test)]
+#[bench]
+fn bench_eagain_always_allocate(b: &mut ::test::Bencher) {
+    b.iter(|| io::Error::new(io::ErrorKind::WouldBlock, errno_to_err_msg(::test::black_box(libc::EAGAIN))));
+}
+
+#[cfg(test)]
+#[bench]
+fn bench_eagain_fast_path(b: &mut ::test::Bencher) {
+    b.iter(|| errno_to_error(::test::black_box(libc::EAGAIN)));
+}
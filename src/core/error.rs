@@ -0,0 +1,106 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use core::errno::{errno_name, last_errno};
+
+/// errors of this crate which aren't a plain `io::Error`, convert into `io::Error` for the io::Result based API
+pub enum PipeError {
+    /// syscall failed with this errno
+    Os(i32),
+    /// a sequenced reader saw `got` while it expected `expected`, the frames in between are gone
+    MissedMessages { expected: u64, got: u64 },
+    /// a `ShutdownHandle` was signaled while the read was waiting
+    Shutdown,
+    /// the path has a NUL byte at `offset`, it can't be passed to a syscall as a C string
+    NulInPath { offset: usize },
+}
+
+impl PipeError {
+    pub fn last_os_error() -> Self {
+        PipeError::Os(last_errno())
+    }
+
+    pub fn errno(&self) -> Option<i32> {
+        match *self {
+            PipeError::Os(errno) => Some(errno),
+            _ => None,
+        }
+    }
+
+    /// the `PipeError` carried inside an `io::Error` returned by this crate, if any
+    pub fn downcast(err: &io::Error) -> Option<&PipeError> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<PipeError>())
+    }
+}
+
+/// symbolic errno name next to the number, so logs stay greppable whatever the locale of strerror is
+impl fmt::Debug for PipeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PipeError::Os(errno) => f
+                .debug_struct("Os")
+                .field("errno", &errno)
+                .field("name", &errno_name(errno))
+                .finish(),
+            PipeError::MissedMessages { expected, got } => f
+                .debug_struct("MissedMessages")
+                .field("expected", &expected)
+                .field("got", &got)
+                .finish(),
+            PipeError::Shutdown => f.write_str("Shutdown"),
+            PipeError::NulInPath { offset } => f.debug_struct("NulInPath").field("offset", &offset).finish(),
+        }
+    }
+}
+
+impl fmt::Display for PipeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PipeError::Os(errno) => write!(f, "{} ({})", io::Error::from_raw_os_error(errno), errno_name(errno)),
+            // a restarted writer counts from 0 again, that's no count of lost messages
+            PipeError::MissedMessages { expected, got } if got < expected => {
+                write!(f, "seqno went back from {} to {}", expected, got)
+            }
+            PipeError::MissedMessages { expected, got } => write!(
+                f,
+                "missed {} message(s): expected seqno {} but got {}",
+                got - expected,
+                expected,
+                got
+            ),
+            PipeError::Shutdown => f.write_str("reader was shut down"),
+            PipeError::NulInPath { offset } => write!(f, "path contains a nul byte at offset {}", offset),
+        }
+    }
+}
+
+impl Error for PipeError {}
+
+impl From<PipeError> for io::Error {
+    fn from(err: PipeError) -> io::Error {
+        match err {
+            PipeError::Os(errno) => io::Error::from_raw_os_error(errno),
+            PipeError::MissedMessages { .. } => io::Error::new(io::ErrorKind::InvalidData, err),
+            // not Interrupted, read_exact and friends would just retry that
+            PipeError::Shutdown => io::Error::new(io::ErrorKind::ConnectionAborted, err),
+            PipeError::NulInPath { .. } => io::Error::new(io::ErrorKind::InvalidInput, err),
+        }
+    }
+}
+
+#[test]
+fn test_pipe_error_debug_has_errno_name() {
+    let err = PipeError::Os(libc::ENXIO);
+    assert_eq!(format!("{:?}", err), "Os { errno: 6, name: \"ENXIO\" }");
+    assert!(err.to_string().ends_with("(ENXIO)"));
+    assert_eq!(io::Error::from(err).raw_os_error(), Some(libc::ENXIO));
+}
+
+#[test]
+fn test_missed_messages_display() {
+    let gap = PipeError::MissedMessages { expected: 3, got: 5 };
+    assert_eq!(gap.to_string(), "missed 2 message(s): expected seqno 3 but got 5");
+    let reset = PipeError::MissedMessages { expected: 40, got: 0 };
+    assert_eq!(reset.to_string(), "seqno went back from 40 to 0");
+}
@@ -0,0 +1,79 @@
+//! kqueue counterpart of the poll based waits, for BSD/macOS event loops
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+use std::time::Duration;
+
+use libc;
+
+use core::reader::PipeReader;
+use core::sys::cvt;
+
+impl PipeReader {
+    /// add an `EVFILT_READ` filter for this pipe to the kqueue `kq`, level triggered like poll
+    pub fn register_kqueue(&self, kq: RawFd) -> io::Result<()> {
+        let mut change = unsafe { std::mem::zeroed::<libc::kevent>() };
+        change.ident = self.as_raw_fd() as libc::uintptr_t;
+        change.filter = libc::EVFILT_READ;
+        change.flags = libc::EV_ADD | libc::EV_ENABLE;
+        cvt(unsafe { libc::kevent(kq, &change, 1, ptr::null_mut(), 0, ptr::null()) })?;
+        Ok(())
+    }
+}
+
+/// a readable pipe reported by `drain_kqueue`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KqueueEvent {
+    pub fd: RawFd,
+    /// bytes readable right now
+    pub available: usize,
+    /// the writer hung up (`EV_EOF`)
+    pub eof: bool,
+}
+
+/// collect up to `max` pending events of `kq`, waiting at most `timeout` (`None` blocks) for the first one
+pub fn drain_kqueue(kq: RawFd, max: usize, timeout: Option<Duration>) -> io::Result<Vec<KqueueEvent>> {
+    let mut events = vec![unsafe { std::mem::zeroed::<libc::kevent>() }; max.max(1)];
+    let timespec = timeout.map(|timeout| libc::timespec {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as libc::c_long,
+    });
+    let timespec_ptr = match timespec {
+        Some(ref timespec) => timespec as *const libc::timespec,
+        None => ptr::null(),
+    };
+    let n = loop {
+        match cvt(unsafe {
+            libc::kevent(kq, ptr::null(), 0, events.as_mut_ptr(), events.len() as libc::c_int, timespec_ptr)
+        }) {
+            Ok(n) => break n as usize,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    };
+    Ok(events[..n]
+        .iter()
+        .map(|event| KqueueEvent {
+            fd: event.ident as RawFd,
+            available: event.data as usize,
+            eof: event.flags & libc::EV_EOF != 0,
+        })
+        .collect())
+}
+
+#[test]
+fn test_register_kqueue() {
+    use std::io::Write;
+
+    let (pipe, reader, mut writer) = ::test_pair("kqueue");
+    let kq = cvt(unsafe { libc::kqueue() }).unwrap();
+    reader.register_kqueue(kq).unwrap();
+    assert!(drain_kqueue(kq, 8, Some(Duration::from_millis(10))).unwrap().is_empty());
+    writer.write_all(b"hello").unwrap();
+    let events = drain_kqueue(kq, 8, Some(Duration::from_secs(1))).unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].fd, reader.as_raw_fd());
+    assert_eq!(events[0].available, 5);
+    unsafe { libc::close(kq) };
+    std::fs::remove_file(pipe.path()).unwrap();
+}
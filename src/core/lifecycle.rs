@@ -0,0 +1,121 @@
+/*!
+who creates and who removes a FIFO shared by several processes
+
+exactly one side `claim`s the path: it creates the FIFO and unlinks it on drop, every other side `attach`es and never
+unlinks. A peer which opens the path after the unlink would create or wait on a different, new FIFO, and a FIFO
+unlinked while still open lives on orphaned until its last fd closes
+
+the owner only unlinks once none of its own fds refer to the FIFO anymore, found by comparing device and inode of
+every fd in `/proc/self/fd` (`/dev/fd` elsewhere). Another process holding the FIFO open can't be seen that way:
+reading its `/proc/<pid>/fd` needs the same uid or `CAP_SYS_PTRACE` and races with its opens anyway, so the
+protocol above (owner outlives the attachers) is what really keeps the path valid
+*/
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+use libc;
+
+use core::pipe::NamedPipe;
+
+#[cfg(target_os = "linux")]
+const FD_DIR: &str = "/proc/self/fd";
+#[cfg(not(target_os = "linux"))]
+const FD_DIR: &str = "/dev/fd";
+
+#[derive(Debug)]
+pub struct PipeLifecycle {
+    pipe: NamedPipe,
+    owner: bool,
+}
+
+impl PipeLifecycle {
+    /// create the FIFO (an existing FIFO is taken over) and become the one to unlink it
+    pub fn claim<P: AsRef<Path>>(path: P, mode: u32) -> io::Result<Self> {
+        let pipe = NamedPipe::new(path);
+        pipe.create(mode)?;
+        Ok(PipeLifecycle { pipe, owner: true })
+    }
+
+    /// use a FIFO somebody else claimed, `NotFound` if it isn't there (yet)
+    pub fn attach<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let pipe = NamedPipe::new(path);
+        if !pipe.is_fifo()? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a FIFO", pipe.path().display()),
+            ));
+        }
+        Ok(PipeLifecycle { pipe, owner: false })
+    }
+
+    pub fn pipe(&self) -> &NamedPipe {
+        &self.pipe
+    }
+
+    pub fn is_owner(&self) -> bool {
+        self.owner
+    }
+
+    /// whether an fd of this process still refers to the FIFO, see the module doc for what this can't see
+    pub fn has_open_handles(&self) -> io::Result<bool> {
+        let fifo = fs::metadata(self.pipe.path())?;
+        for entry in fs::read_dir(FD_DIR)? {
+            let fd: RawFd = match entry?.file_name().to_str().and_then(|name| name.parse().ok()) {
+                Some(fd) => fd,
+                None => continue,
+            };
+            let mut fd_stat = unsafe { std::mem::zeroed::<libc::stat>() };
+            // the fd read_dir itself uses is gone by now, EBADF just means closed in between
+            if unsafe { libc::fstat(fd, &mut fd_stat) } != 0 {
+                continue;
+            }
+            if fd_stat.st_dev as u64 == fifo.dev() && fd_stat.st_ino as u64 == fifo.ino() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl Drop for PipeLifecycle {
+    fn drop(&mut self) {
+        if !self.owner {
+            return;
+        }
+        // left on disk rather than orphaning a FIFO this process still uses
+        if let Ok(false) = self.has_open_handles() {
+            let _ = fs::remove_file(self.pipe.path());
+        }
+    }
+}
+
+#[test]
+fn test_only_owner_unlinks() {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let path = std::env::temp_dir().join(format!("rust_pipe_{}_lifecycle", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let owner = PipeLifecycle::claim(&path, 0o600).unwrap();
+    let attacher = PipeLifecycle::attach(&path).unwrap();
+    assert!(owner.is_owner() && !attacher.is_owner());
+    drop(attacher);
+    assert!(owner.pipe().is_fifo().unwrap());
+    drop(owner);
+    assert!(!path.exists());
+
+    // an fd of our own on the FIFO keeps it on disk
+    let owner = PipeLifecycle::claim(&path, 0o600).unwrap();
+    let handle = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&path)
+        .unwrap();
+    assert!(owner.has_open_handles().unwrap());
+    drop(owner);
+    assert!(NamedPipe::new(&path).is_fifo().unwrap());
+    drop(handle);
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,107 @@
+/*!
+the syscall layer: mkfifo, open, read/write on the typed handles and errno, always built and free of optional deps
+
+the helpers on top of it (buffering, pipe sizes, selectors, retries, clocks, duplex, splice, ...) and framing sit
+behind cargo features, see `Cargo.toml`
+*/
+#[cfg(all(feature = "capacity", target_os = "linux"))]
+mod capacity;
+mod clock;
+#[cfg(feature = "config")]
+mod config;
+#[cfg(feature = "buffered")]
+mod cstr;
+#[cfg(feature = "duplex")]
+pub(crate) mod duplex;
+pub(crate) mod errno;
+pub(crate) mod error;
+#[cfg(all(feature = "select", any(target_os = "macos", target_os = "freebsd")))]
+mod kqueue;
+#[cfg(feature = "lifecycle")]
+mod lifecycle;
+#[cfg(feature = "lifecycle")]
+mod owned;
+#[cfg(all(feature = "packet", target_os = "linux"))]
+mod packet;
+pub(crate) mod pipe;
+#[cfg(feature = "poll")]
+mod poll;
+#[cfg(feature = "duplex")]
+mod rdwr;
+pub(crate) mod reader;
+#[cfg(feature = "retry")]
+mod reconnect;
+#[cfg(feature = "report")]
+mod report;
+#[cfg(feature = "retry")]
+pub(crate) mod retry;
+#[cfg(feature = "secure")]
+mod secure;
+#[cfg(feature = "select")]
+mod selector;
+#[cfg(feature = "select")]
+mod shutdown;
+#[cfg(feature = "signals")]
+mod sigpipe;
+mod spin;
+#[cfg(all(feature = "splice", target_os = "linux"))]
+mod splice;
+#[cfg(feature = "stdio")]
+mod stdio;
+pub(crate) mod sys;
+#[cfg(feature = "splice")]
+mod tee;
+#[cfg(feature = "lifecycle")]
+mod watchdog;
+pub(crate) mod writer;
+#[cfg(all(feature = "capacity", target_os = "linux"))]
+pub use self::capacity::CapacityGrant;
+#[cfg(feature = "clock")]
+pub use self::clock::{Clock, MockClock, SystemClock};
+#[cfg(feature = "config")]
+pub use self::config::NamedPipeConfig;
+#[cfg(feature = "duplex")]
+pub use self::duplex::Duplex;
+#[cfg(feature = "inspect")]
+pub use self::errno::errno_name;
+pub use self::errno::errno_to_error;
+pub use self::error::PipeError;
+#[cfg(all(feature = "select", any(target_os = "macos", target_os = "freebsd")))]
+pub use self::kqueue::{drain_kqueue, KqueueEvent};
+#[cfg(feature = "lifecycle")]
+pub use self::lifecycle::PipeLifecycle;
+#[cfg(feature = "lifecycle")]
+pub use self::owned::OwnedPipe;
+#[cfg(feature = "inspect")]
+pub use self::pipe::PathKind;
+pub use self::pipe::{raw_mkfifo, NamedPipe, Pipe};
+#[cfg(feature = "poll")]
+pub use self::poll::PollFlags;
+#[cfg(feature = "duplex")]
+pub use self::rdwr::PipeDuplexEnd;
+#[cfg(feature = "buffered")]
+pub use self::reader::BufPipeReader;
+pub use self::reader::PipeReader;
+#[cfg(feature = "retry")]
+pub use self::reconnect::ReconnectingWriter;
+#[cfg(feature = "report")]
+pub use self::report::PipeReport;
+#[cfg(feature = "retry")]
+pub use self::retry::RetryPolicy;
+#[cfg(feature = "select")]
+pub use self::selector::{Backend, PipeSelector, EPOLL_THRESHOLD};
+#[cfg(feature = "select")]
+pub use self::shutdown::{ShutdownHandle, ShutdownReader};
+#[cfg(feature = "signals")]
+pub use self::sigpipe::{ignore_sigpipe, ignore_sigpipe_permanent, SigPipeGuard};
+#[cfg(feature = "retry")]
+pub use self::spin::SpinPolicy;
+#[cfg(all(feature = "splice", target_os = "linux"))]
+pub use self::splice::{splice_between, tee_between};
+#[cfg(feature = "splice")]
+pub use self::tee::TeeReader;
+#[cfg(feature = "lifecycle")]
+pub use self::watchdog::OpenWatchdog;
+pub use self::writer::PipeWriter;
+#[cfg(feature = "stats")]
+pub use self::writer::WriteStats;
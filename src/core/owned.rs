@@ -0,0 +1,113 @@
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use libc;
+
+use core::pipe::{raw_mkfifo, NamedPipe};
+use core::sys::path_to_cstring;
+
+/// name collisions tolerated before `create_temp` gives up
+const TEMP_ATTEMPTS: usize = 16;
+
+/// a FIFO this process created, unlinked from the filesystem on drop
+#[derive(Debug)]
+pub struct OwnedPipe {
+    pipe: NamedPipe,
+    keep: bool,
+}
+
+impl OwnedPipe {
+    /// take over an existing FIFO path, it will be removed on drop
+    pub fn new(pipe: NamedPipe) -> Self {
+        OwnedPipe { pipe, keep: false }
+    }
+
+    /// stop owning the path, the FIFO stays on disk
+    pub fn into_inner(mut self) -> NamedPipe {
+        self.keep = true;
+        self.pipe.clone()
+    }
+}
+
+impl Deref for OwnedPipe {
+    type Target = NamedPipe;
+
+    fn deref(&self) -> &NamedPipe {
+        &self.pipe
+    }
+}
+
+impl Drop for OwnedPipe {
+    fn drop(&mut self) {
+        if !self.keep {
+            // already gone is fine, nothing useful to do with other errors in drop
+            let _ = std::fs::remove_file(self.pipe.path());
+        }
+    }
+}
+
+impl NamedPipe {
+    /**
+    create a FIFO named `pipe-<random>` in `dir`, or `$XDG_RUNTIME_DIR` falling back to the temp dir
+
+    a name which already exists is just retried with a fresh one, the returned `OwnedPipe` unlinks it on drop
+    */
+    pub fn create_temp(dir: Option<&Path>, mode: u32) -> io::Result<OwnedPipe> {
+        let dir = match dir {
+            Some(dir) => dir.to_path_buf(),
+            None => default_temp_dir(),
+        };
+        for _ in 0..TEMP_ATTEMPTS {
+            let path = dir.join(format!("pipe-{:016x}", random_u64()));
+            match raw_mkfifo(&path_to_cstring(&path)?, mode as libc::mode_t) {
+                Ok(()) => return Ok(OwnedPipe::new(NamedPipe::new(path))),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("no unused pipe name in {} after {} attempts", dir.display(), TEMP_ATTEMPTS),
+        ))
+    }
+}
+
+fn default_temp_dir() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => env::temp_dir(),
+    }
+}
+
+/// not crypto grade, the randomly seeded std hasher over a counter and the clock is plenty for unique names
+fn random_u64() -> u64 {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(COUNTER.fetch_add(1, Ordering::Relaxed));
+    if let Ok(since_epoch) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(since_epoch.as_nanos());
+    }
+    hasher.write_u32(std::process::id());
+    hasher.finish()
+}
+
+#[test]
+fn test_create_temp_unique_and_cleaned_up() {
+    let dir = env::temp_dir();
+    let first = NamedPipe::create_temp(Some(&dir), 0o600).unwrap();
+    let second = NamedPipe::create_temp(Some(&dir), 0o600).unwrap();
+    assert_ne!(first.path(), second.path());
+    assert!(first.is_fifo().unwrap());
+    assert!(second.is_fifo().unwrap());
+    let paths = (first.path().to_path_buf(), second.path().to_path_buf());
+    drop(first);
+    drop(second);
+    assert!(!paths.0.exists());
+    assert!(!paths.1.exists());
+}
@@ -0,0 +1,110 @@
+/*!
+packet mode pipes (Linux only): with `O_DIRECT` on the write end every `write` becomes one packet and every `read`
+returns at most one packet, message boundaries without a length prefix
+
+- a packet is at most `PIPE_BUF` (4096) bytes, longer writes would be split into several packets
+- a `read_packet` buffer shorter than the packet truncates it, the rest of that packet is discarded by the kernel
+- `open(2)` refuses `O_DIRECT` on a FIFO with `EINVAL`, so the flag is switched on with `fcntl(F_SETFL)` after opening.
+  Only the writer needs it, the packet boundaries are kept in the pipe buffer for whichever reader
+*/
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use libc;
+
+use core::pipe::NamedPipe;
+use core::reader::PipeReader;
+use core::sys::cvt;
+use core::writer::PipeWriter;
+
+impl NamedPipe {
+    /// blocking `open_writer` with packet mode switched on
+    pub fn open_packet_writer(&self) -> io::Result<PipeWriter> {
+        let writer = self.open_writer()?;
+        writer.set_packet_mode(true)?;
+        Ok(writer)
+    }
+}
+
+impl PipeWriter {
+    /// toggle `O_DIRECT` on this end, later writes become packets (or stop being packets)
+    pub fn set_packet_mode(&self, packet: bool) -> io::Result<()> {
+        let fd = self.as_raw_fd();
+        let flags = cvt(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+        let new_flags = if packet { flags | libc::O_DIRECT } else { flags & !libc::O_DIRECT };
+        if new_flags != flags {
+            cvt(unsafe { libc::fcntl(fd, libc::F_SETFL, new_flags) })?;
+        }
+        Ok(())
+    }
+
+    pub fn is_packet_mode(&self) -> io::Result<bool> {
+        Ok(cvt(unsafe { libc::fcntl(self.as_raw_fd(), libc::F_GETFL) })? & libc::O_DIRECT != 0)
+    }
+
+    /// write `packet` as a single packet, longer than `PIPE_BUF` is rejected with `InvalidInput` instead of being split
+    pub fn write_packet(&mut self, packet: &[u8]) -> io::Result<()> {
+        if packet.len() > libc::PIPE_BUF {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("packet of {} bytes is larger than PIPE_BUF {}", packet.len(), libc::PIPE_BUF),
+            ));
+        }
+        let written = loop {
+            match io::Write::write(self, packet) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                res => break res?,
+            }
+        };
+        if written != packet.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                format!("packet write was cut to {} of {} bytes", written, packet.len()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl PipeReader {
+    /**
+    read one packet into `buf`, returns its length and `Ok(0)` at EOF
+
+    a `buf` shorter than the packet gets its start, the rest of that packet is gone
+    */
+    pub fn read_packet(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match io::Read::read(self, buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                res => return res,
+            }
+        }
+    }
+}
+
+#[test]
+fn test_packets_keep_boundaries() {
+    let pipe = ::test_pipe("packet_mode");
+    let reader = {
+        let pipe = pipe.clone();
+        std::thread::spawn(move || pipe.open_reader().unwrap())
+    };
+    let mut writer = pipe.open_packet_writer().unwrap();
+    let mut reader = reader.join().unwrap();
+    assert!(writer.is_packet_mode().unwrap());
+    writer.write_packet(b"first").unwrap();
+    writer.write_packet(b"second packet").unwrap();
+    writer.write_packet(b"truncated").unwrap();
+    drop(writer);
+
+    let mut buf = [0u8; 64];
+    let n = reader.read_packet(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"first");
+    let n = reader.read_packet(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"second packet");
+    // a short buffer drops the rest of the packet
+    let n = reader.read_packet(&mut buf[..5]).unwrap();
+    assert_eq!(&buf[..n], b"trunc");
+    assert_eq!(reader.read_packet(&mut buf).unwrap(), 0);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
@@ -0,0 +1,484 @@
+use std::ffi::CStr;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libc;
+
+#[cfg(all(feature = "retry", feature = "clock"))]
+use core::clock::Clock;
+use core::errno::errno_location;
+use core::reader::PipeReader;
+#[cfg(feature = "retry")]
+use core::retry::RetryPolicy;
+use core::sys::{cvt, path_to_cstring, set_nonblocking};
+use core::writer::PipeWriter;
+
+/// fallback when `pathconf` reports no limit for the parent directory
+const DEFAULT_NAME_MAX: usize = 255;
+
+/// A named pipe (FIFO) identified by its filesystem path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamedPipe {
+    path: PathBuf,
+}
+
+impl NamedPipe {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        NamedPipe {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /**
+    mkfifo with the given permission bits, a FIFO already at the path counts as success
+
+    there is no exists() pre-check, which would race with another creator.
+    Instead `EEXIST` is looked at afterwards and only an error if the file there is not a FIFO
+    */
+    pub fn create(&self, mode: u32) -> io::Result<()> {
+        let path = path_to_cstring(&self.path)?;
+        match raw_mkfifo(&path, mode as libc::mode_t) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.raw_os_error() == Some(libc::EEXIST) => match PathKind::from_mode(self.raw_stat()?.st_mode) {
+                PathKind::Fifo => Ok(()),
+                kind => Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists: expected FIFO, found {}", self.path.display(), kind),
+                )),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /**
+    what is at the path right now, without following a symlink there
+
+    for a precise diagnosis where `is_fifo` just says `false`, e.g. "expected FIFO, found socket"
+    */
+    #[cfg(feature = "inspect")]
+    pub fn path_kind(&self) -> io::Result<PathKind> {
+        let path = path_to_cstring(&self.path)?;
+        let mut file_stat = unsafe { std::mem::zeroed::<libc::stat>() };
+        match cvt(unsafe { libc::lstat(path.as_ptr(), &mut file_stat) }) {
+            Ok(_) => Ok(PathKind::from_mode(file_stat.st_mode)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(PathKind::Missing),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn is_fifo(&self) -> io::Result<bool> {
+        let file_stat = self.raw_stat()?;
+        // S_ISFIFO in /usr/include/sys/stat.h
+        Ok(file_stat.st_mode & libc::S_IFMT == libc::S_IFIFO)
+    }
+
+    /**
+    time since the FIFO was last touched: the later of `st_mtime` (last write) and `st_ctime` (creation, chmod, ...)
+
+    a clock which went backwards gives zero instead of an error, a time before 1970 is just old
+    */
+    pub fn age(&self) -> io::Result<Duration> {
+        let file_stat = self.raw_stat()?;
+        let mtime = stat_time(file_stat.st_mtime, file_stat.st_mtime_nsec);
+        let ctime = stat_time(file_stat.st_ctime, file_stat.st_ctime_nsec);
+        Ok(SystemTime::now().duration_since(mtime.max(ctime)).unwrap_or_default())
+    }
+
+    /// whether the FIFO is older than `max_age`, e.g. left over by a crashed previous run
+    pub fn is_stale(&self, max_age: Duration) -> io::Result<bool> {
+        Ok(self.age()? > max_age)
+    }
+
+    /**
+    the whole `libc::stat` of the path, following symlinks, for fields nothing else here exposes (`st_ino`, `st_dev`,
+    `st_blksize`, `st_nlink`, ...)
+
+    a low-level escape hatch, the layout is libc's and differs per target
+    */
+    pub fn raw_stat(&self) -> io::Result<libc::stat> {
+        let path = path_to_cstring(&self.path)?;
+        // or use std::fs::File::metadata(&self)
+        let mut file_stat = unsafe { std::mem::zeroed::<libc::stat>() };
+        cvt(unsafe { libc::stat(path.as_ptr(), &mut file_stat) })?;
+        Ok(file_stat)
+    }
+
+    /// blocks until a writer opens the other end
+    pub fn open_reader(&self) -> io::Result<PipeReader> {
+        Ok(PipeReader::from_file(File::open(&self.path)?))
+    }
+
+    /// blocks until a reader opens the other end
+    pub fn open_writer(&self) -> io::Result<PipeWriter> {
+        let file = OpenOptions::new().write(true).open(&self.path)?;
+        Ok(PipeWriter::from_file(file).with_origin(self))
+    }
+
+    /**
+    open the write end without waiting for a reader
+
+    fails with `ENXIO` if no reader has the FIFO open, see `man 7 fifo`. On success the fd is switched back to blocking mode
+    */
+    pub fn try_open_writer(&self) -> io::Result<PipeWriter> {
+        // Non-Blocking open: https://docs.rs/unix-named-pipe/0.2.0/src/unix_named_pipe/lib.rs.html#91
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)?;
+        set_nonblocking(file.as_raw_fd(), false)?;
+        Ok(PipeWriter::from_file(file).with_origin(self))
+    }
+
+    /// `try_open_writer` until a reader shows up (`ENXIO`) or the FIFO gets created (`ENOENT`), backing off per `policy`
+    #[cfg(feature = "retry")]
+    pub fn connect_with_retry(&self, policy: &RetryPolicy) -> io::Result<PipeWriter> {
+        policy.retry(|| self.try_open_writer(), is_peer_missing)
+    }
+
+    /// `connect_with_retry` waiting on `clock`
+    #[cfg(all(feature = "retry", feature = "clock"))]
+    pub fn connect_with_retry_with_clock(&self, policy: &RetryPolicy, clock: &dyn Clock) -> io::Result<PipeWriter> {
+        policy.retry_with_clock(|| self.try_open_writer(), is_peer_missing, clock)
+    }
+
+    /**
+    Check the path against `PATH_MAX` and the last component against `pathconf(parent, _PC_NAME_MAX)`
+
+    mkfifo would fail with `ENAMETOOLONG` anyway, but this gives an `InvalidInput` error naming the limit before any syscall touches the filesystem
+    */
+    pub fn validate(&self) -> io::Result<()> {
+        let path_len = self.path.as_os_str().len();
+        // PATH_MAX counts the \0 terminator
+        if path_len + 1 > libc::PATH_MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("pipe path is too long: {} bytes exceeds PATH_MAX {}", path_len, libc::PATH_MAX),
+            ));
+        }
+        let name = match self.path.file_name() {
+            Some(name) => name,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("pipe path {} has no file name", self.path.display()),
+                ))
+            }
+        };
+        let parent = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let name_max = name_max(parent)?;
+        if name.len() > name_max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "pipe name is too long: {} bytes exceeds NAME_MAX {} of {}",
+                    name.len(),
+                    name_max,
+                    parent.display()
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/**
+exactly one mkfifo(3) call: no existence check, no validation, `EEXIST` is an error like any other
+
+escape hatch under `NamedPipe::create`, the mode is still filtered by the process umask
+*/
+pub fn raw_mkfifo(path: &CStr, mode: libc::mode_t) -> io::Result<()> {
+    // https://docs.rs/nix/0.21.0/nix/unistd/fn.mkfifo.html
+    // permission bit: https://www.gnu.org/software/libc/manual/html_node/Permission-Bits.html
+    cvt(unsafe { libc::mkfifo(path.as_ptr(), mode) })?;
+    Ok(())
+}
+
+/// namespace for pipes without a path
+pub enum Pipe {}
+
+/// file type of a path, from the `S_IFMT` bits of `lstat`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+// without the inspect feature it only names what `create` found in the way
+#[cfg_attr(not(feature = "inspect"), allow(dead_code))]
+pub enum PathKind {
+    Fifo,
+    Regular,
+    Directory,
+    CharDevice,
+    BlockDevice,
+    Socket,
+    Symlink,
+    Missing,
+}
+
+impl PathKind {
+    pub(crate) fn from_mode(st_mode: libc::mode_t) -> Self {
+        match st_mode & libc::S_IFMT {
+            libc::S_IFIFO => PathKind::Fifo,
+            libc::S_IFDIR => PathKind::Directory,
+            libc::S_IFCHR => PathKind::CharDevice,
+            libc::S_IFBLK => PathKind::BlockDevice,
+            libc::S_IFSOCK => PathKind::Socket,
+            libc::S_IFLNK => PathKind::Symlink,
+            // S_IFREG, and whatever exotic type a filesystem may come up with is closest to a plain file
+            _ => PathKind::Regular,
+        }
+    }
+}
+
+impl fmt::Display for PathKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            PathKind::Fifo => "FIFO",
+            PathKind::Regular => "regular file",
+            PathKind::Directory => "directory",
+            PathKind::CharDevice => "character device",
+            PathKind::BlockDevice => "block device",
+            PathKind::Socket => "socket",
+            PathKind::Symlink => "symlink",
+            PathKind::Missing => "nothing",
+        })
+    }
+}
+
+impl Pipe {
+    /**
+    anonymous pipe(2), e.g. shared by parent and child across `fork`, both fds are close-on-exec
+
+    the ends are the same typed handles a FIFO gives, so framing and timeouts work the same way
+    */
+    pub fn anonymous() -> io::Result<(PipeReader, PipeWriter)> {
+        let mut fds = [0 as libc::c_int; 2];
+        pipe_cloexec(&mut fds)?;
+        Ok(unsafe { (PipeReader::from_raw_fd(fds[0]), PipeWriter::from_raw_fd(fds[1])) })
+    }
+}
+
+#[cfg(not(any(target_os = "ios", target_os = "macos")))]
+fn pipe_cloexec(fds: &mut [libc::c_int; 2]) -> io::Result<()> {
+    cvt(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) })?;
+    Ok(())
+}
+
+/// no pipe2 on apple platforms, set FD_CLOEXEC right after
+#[cfg(any(target_os = "ios", target_os = "macos"))]
+fn pipe_cloexec(fds: &mut [libc::c_int; 2]) -> io::Result<()> {
+    cvt(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+    for &fd in fds.iter() {
+        cvt(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+    }
+    Ok(())
+}
+
+/// `st_*time` seconds and nanoseconds as a `SystemTime`, negative seconds are before the epoch, one out of range the epoch itself
+fn stat_time<S: Into<i64>, N: Into<i64>>(secs: S, nsec: N) -> SystemTime {
+    // time_t and c_long are 32 bit on some targets
+    let (secs, nsec) = (secs.into(), nsec.into());
+    let whole = Duration::from_secs(secs.unsigned_abs());
+    let at = if secs < 0 { UNIX_EPOCH.checked_sub(whole) } else { UNIX_EPOCH.checked_add(whole) };
+    at.and_then(|at| at.checked_add(Duration::from_nanos(nsec.max(0) as u64)))
+        .unwrap_or(UNIX_EPOCH)
+}
+
+/// errors meaning the other side just isn't there yet
+#[cfg(feature = "retry")]
+pub(crate) fn is_peer_missing(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::ENXIO) | Some(libc::ENOENT))
+}
+
+fn name_max(dir: &Path) -> io::Result<usize> {
+    let dir = path_to_cstring(dir)?;
+    // pathconf returns -1 both on error and for "no limit", only errno tells them apart
+    unsafe { *errno_location() = 0 };
+    let ret = unsafe { libc::pathconf(dir.as_ptr(), libc::_PC_NAME_MAX) };
+    if ret == -1 {
+        let errno = unsafe { *errno_location() };
+        if errno != 0 {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+        return Ok(DEFAULT_NAME_MAX);
+    }
+    Ok(ret as usize)
+}
+
+#[test]
+fn test_raw_stat_is_fifo() {
+    let pipe = ::test_pipe("raw_stat");
+    let file_stat = pipe.raw_stat().unwrap();
+    assert!(file_stat.st_mode & libc::S_IFIFO != 0);
+    assert_eq!(file_stat.st_nlink, 1);
+    std::fs::remove_file(pipe.path()).unwrap();
+    assert_eq!(pipe.raw_stat().unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_validate_name_too_long() {
+    let name = "p".repeat(300);
+    let pipe = NamedPipe::new(std::env::temp_dir().join(name));
+    let err = pipe.validate().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(err.to_string().contains("name is too long"), "{}", err);
+}
+
+#[test]
+fn test_validate_ok() {
+    let pipe = NamedPipe::new(std::env::temp_dir().join("my_pipe"));
+    pipe.validate().unwrap();
+}
+
+#[test]
+fn test_create_race() {
+    let path = std::env::temp_dir().join(format!("rust_pipe_{}_create_race", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let pipe = NamedPipe::new(&path);
+    let barrier = std::sync::Arc::new(std::sync::Barrier::new(16));
+    let creators: Vec<_> = (0..16)
+        .map(|_| {
+            let pipe = pipe.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                barrier.wait();
+                pipe.create(0o600)
+            })
+        })
+        .collect();
+    for creator in creators {
+        creator.join().unwrap().unwrap();
+    }
+    assert!(pipe.is_fifo().unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_raw_mkfifo_not_idempotent() {
+    let path = std::env::temp_dir().join(format!("rust_pipe_{}_raw_mkfifo", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let c_path = path_to_cstring(&path).unwrap();
+    raw_mkfifo(&c_path, 0o600).unwrap();
+    let err = raw_mkfifo(&c_path, 0o600).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_create_over_regular_file() {
+    let path = std::env::temp_dir().join(format!("rust_pipe_{}_create_regular", std::process::id()));
+    std::fs::write(&path, b"").unwrap();
+    let err = NamedPipe::new(&path).create(0o600).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert!(err.to_string().ends_with("expected FIFO, found regular file"), "{}", err);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "inspect")]
+#[test]
+fn test_path_kind() {
+    let dir = std::env::temp_dir();
+    let pid = std::process::id();
+    let fifo = ::test_pipe("path_kind");
+    assert_eq!(fifo.path_kind().unwrap(), PathKind::Fifo);
+
+    let regular = dir.join(format!("rust_pipe_{}_kind_regular", pid));
+    std::fs::write(&regular, b"").unwrap();
+    assert_eq!(NamedPipe::new(&regular).path_kind().unwrap(), PathKind::Regular);
+
+    let socket = dir.join(format!("rust_pipe_{}_kind_socket", pid));
+    let _ = std::fs::remove_file(&socket);
+    let listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+    assert_eq!(NamedPipe::new(&socket).path_kind().unwrap(), PathKind::Socket);
+    drop(listener);
+
+    // the link itself, not the FIFO it points to
+    let symlink = dir.join(format!("rust_pipe_{}_kind_symlink", pid));
+    let _ = std::fs::remove_file(&symlink);
+    std::os::unix::fs::symlink(fifo.path(), &symlink).unwrap();
+    assert_eq!(NamedPipe::new(&symlink).path_kind().unwrap(), PathKind::Symlink);
+    assert!(NamedPipe::new(&symlink).is_fifo().unwrap());
+
+    assert_eq!(NamedPipe::new(&dir).path_kind().unwrap(), PathKind::Directory);
+    assert_eq!(NamedPipe::new("/dev/null").path_kind().unwrap(), PathKind::CharDevice);
+    let missing = NamedPipe::new(dir.join(format!("rust_pipe_{}_kind_missing", pid)));
+    assert_eq!(missing.path_kind().unwrap(), PathKind::Missing);
+
+    for path in &[fifo.path(), &regular, &socket, &symlink] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_age_and_is_stale() {
+    let pipe = ::test_pipe("age");
+    let slept = Duration::from_millis(50);
+    std::thread::sleep(slept);
+    assert!(pipe.age().unwrap() >= slept);
+    assert!(pipe.is_stale(Duration::from_millis(10)).unwrap());
+    assert!(!pipe.is_stale(Duration::from_secs(3600)).unwrap());
+    std::fs::remove_file(pipe.path()).unwrap();
+    assert_eq!(pipe.age().unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_is_stale_before_epoch() {
+    let pipe = ::test_pipe("age_before_epoch");
+    let path = path_to_cstring(pipe.path()).unwrap();
+    let times = [libc::timeval { tv_sec: -86_400, tv_usec: 0 }; 2];
+    cvt(unsafe { libc::utimes(path.as_ptr(), times.as_ptr()) }).unwrap();
+    assert!(pipe.raw_stat().unwrap().st_mtime < 0);
+    // st_ctime was bumped by utimes, so the FIFO is still fresh
+    assert!(!pipe.is_stale(Duration::from_secs(3600)).unwrap());
+    assert_eq!(stat_time(-1i64, 500_000_000i64), UNIX_EPOCH - Duration::from_millis(500));
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[cfg(feature = "retry")]
+#[test]
+fn test_connect_with_retry_gives_up() {
+    let pipe = ::test_pipe("connect_gives_up");
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: std::time::Duration::from_millis(10),
+        max_backoff: std::time::Duration::from_millis(100),
+        multiplier: 2.0,
+        ..RetryPolicy::default()
+    };
+    let start = std::time::Instant::now();
+    let err = pipe.connect_with_retry(&policy).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENXIO));
+    // slept 10ms + 20ms between the three attempts
+    assert!(start.elapsed() >= std::time::Duration::from_millis(30));
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[cfg(feature = "framing")]
+#[test]
+fn test_anonymous_pipe_across_fork() {
+    let (mut reader, mut writer) = Pipe::anonymous().unwrap();
+    let pid = unsafe { libc::fork() };
+    assert_ne!(pid, -1);
+    if pid == 0 {
+        drop(reader);
+        let code = match writer.write_message(b"hello from child") {
+            Ok(()) => 0,
+            Err(_) => 1,
+        };
+        unsafe { libc::_exit(code) };
+    }
+    drop(writer);
+    assert_eq!(reader.read_message().unwrap().unwrap(), b"hello from child");
+    assert_eq!(reader.read_message().unwrap(), None);
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitOrAssign};
+
+use libc;
+
+/// `events`/`revents` bits of poll(2)
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct PollFlags(libc::c_short);
+
+impl PollFlags {
+    /// data to read, or EOF pending after it
+    pub const IN: PollFlags = PollFlags(libc::POLLIN);
+    pub const PRI: PollFlags = PollFlags(libc::POLLPRI);
+    /// room in the pipe buffer
+    pub const OUT: PollFlags = PollFlags(libc::POLLOUT);
+    /// on a write end: every reader is gone, reported even if not asked for
+    pub const ERR: PollFlags = PollFlags(libc::POLLERR);
+    /// all writers closed, reported even if not asked for
+    pub const HUP: PollFlags = PollFlags(libc::POLLHUP);
+    /// the fd isn't open, reported even if not asked for
+    pub const NVAL: PollFlags = PollFlags(libc::POLLNVAL);
+
+    const NAMES: [(PollFlags, &'static str); 6] = [
+        (PollFlags::IN, "IN"),
+        (PollFlags::PRI, "PRI"),
+        (PollFlags::OUT, "OUT"),
+        (PollFlags::ERR, "ERR"),
+        (PollFlags::HUP, "HUP"),
+        (PollFlags::NVAL, "NVAL"),
+    ];
+
+    pub fn empty() -> Self {
+        PollFlags(0)
+    }
+
+    pub fn from_bits(bits: libc::c_short) -> Self {
+        PollFlags(bits)
+    }
+
+    pub fn bits(self) -> libc::c_short {
+        self.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// every bit of `other` is set
+    pub fn contains(self, other: PollFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// any bit of `other` is set
+    pub fn intersects(self, other: PollFlags) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitOr for PollFlags {
+    type Output = PollFlags;
+
+    fn bitor(self, other: PollFlags) -> PollFlags {
+        PollFlags(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for PollFlags {
+    fn bitor_assign(&mut self, other: PollFlags) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitAnd for PollFlags {
+    type Output = PollFlags;
+
+    fn bitand(self, other: PollFlags) -> PollFlags {
+        PollFlags(self.0 & other.0)
+    }
+}
+
+/// `IN | HUP`, `(empty)` for none and unnamed bits in hex
+impl fmt::Debug for PollFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("(empty)");
+        }
+        let mut rest = self.0;
+        let mut first = true;
+        for &(flag, name) in PollFlags::NAMES.iter() {
+            if self.contains(flag) {
+                if !first {
+                    f.write_str(" | ")?;
+                }
+                f.write_str(name)?;
+                first = false;
+                rest &= !flag.0;
+            }
+        }
+        if rest != 0 {
+            if !first {
+                f.write_str(" | ")?;
+            }
+            write!(f, "{:#x}", rest)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_poll_events_in_and_hup() {
+    use std::io::{Read, Write};
+    use std::time::Duration;
+
+    let (pipe, mut reader, mut writer) = ::test_pair("poll_events");
+    let zero = Some(Duration::from_secs(0));
+    assert!(reader.poll_events(PollFlags::IN, zero).unwrap().is_empty());
+    writer.write_all(b"x").unwrap();
+    assert_eq!(reader.poll_events(PollFlags::IN, zero).unwrap(), PollFlags::IN);
+
+    reader.read_exact(&mut [0u8; 1]).unwrap();
+    drop(writer);
+    // HUP comes without being asked for, IN is gone since there is nothing left to read
+    let revents = reader.poll_events(PollFlags::IN, zero).unwrap();
+    assert!(revents.contains(PollFlags::HUP), "{:?}", revents);
+    assert!(!revents.contains(PollFlags::IN), "{:?}", revents);
+    assert_eq!(format!("{:?}", PollFlags::IN | PollFlags::HUP), "IN | HUP");
+    std::fs::remove_file(pipe.path()).unwrap();
+}
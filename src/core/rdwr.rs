@@ -0,0 +1,83 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use core::pipe::NamedPipe;
+use core::reader::PipeReader;
+use core::writer::PipeWriter;
+
+/**
+a FIFO opened `O_RDWR`, reads and writes through the same open file description
+
+the open never blocks since this handle is a reader and a writer at once (Linux behaviour, POSIX leaves it undefined),
+and the pipe never hits EOF while it's open: its own write side keeps the FIFO alive
+*/
+#[derive(Debug)]
+pub struct PipeDuplexEnd {
+    file: File,
+}
+
+impl NamedPipe {
+    pub fn open_rdwr(&self) -> io::Result<PipeDuplexEnd> {
+        let file = OpenOptions::new().read(true).write(true).open(self.path())?;
+        Ok(PipeDuplexEnd { file })
+    }
+}
+
+impl PipeDuplexEnd {
+    /**
+    separate halves for separate threads, the fd is `dup`ed so each one owns its own descriptor
+
+    both still share the open description and so its `O_RDWR`: a read on the `PipeReader` only returns EOF once the
+    `PipeWriter` is dropped as well, not when the other processes' writers are all gone
+    */
+    pub fn into_split(self) -> io::Result<(PipeReader, PipeWriter)> {
+        let dup = self.file.try_clone()?;
+        Ok((PipeReader::from_file(self.file), PipeWriter::from_file(dup)))
+    }
+}
+
+impl Read for PipeDuplexEnd {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for PipeDuplexEnd {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for PipeDuplexEnd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+#[test]
+fn test_into_split_across_threads() {
+    let pipe = ::test_pipe("rdwr_split");
+    let (mut reader, mut writer) = pipe.open_rdwr().unwrap().into_split().unwrap();
+    assert_ne!(reader.as_raw_fd(), writer.as_raw_fd());
+    let producer = std::thread::spawn(move || {
+        for i in 0..10u8 {
+            writer.write_all(&[i; 100]).unwrap();
+        }
+    });
+    let consumer = std::thread::spawn(move || {
+        let mut buf = [0u8; 1000];
+        reader.read_exact(&mut buf).unwrap();
+        buf
+    });
+    producer.join().unwrap();
+    let buf = consumer.join().unwrap();
+    for (i, chunk) in buf.chunks(100).enumerate() {
+        assert!(chunk.iter().all(|&b| b == i as u8));
+    }
+    std::fs::remove_file(pipe.path()).unwrap();
+}
@@ -0,0 +1,540 @@
+use std::fs::File;
+#[cfg(feature = "buffered")]
+use std::io::{BufRead, BufReader};
+use std::io::{self, IoSliceMut, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use libc;
+
+use core::clock::{Clock, SystemClock};
+#[cfg(feature = "poll")]
+use core::poll::PollFlags;
+use core::sys::{access_mode, fionread, poll_fd};
+
+/**
+read end of a FIFO
+
+unbuffered, every read is a syscall on the fd, so whatever works on the raw fd (`splice`, a selector, `into_raw_fd`)
+sees the same bytes. With the buffered feature, `buffered` turns it into a `BufPipeReader` for reading lines or
+delimited records
+*/
+#[derive(Debug)]
+pub struct PipeReader {
+    file: File,
+}
+
+impl PipeReader {
+    pub(crate) fn from_file(file: File) -> Self {
+        PipeReader { file }
+    }
+
+    /**
+    `from_raw_fd` which first checks the access mode with `fcntl(F_GETFL)`
+
+    a write-only fd is rejected with `InvalidInput` instead of failing later with a confusing `EBADF`,
+    on error the fd is not taken over and the caller still has to close it
+
+    # Safety
+    same as `FromRawFd::from_raw_fd`: on success `fd` must be an open fd owned by nobody else
+    */
+    pub unsafe fn from_raw_fd_checked(fd: RawFd) -> io::Result<Self> {
+        if access_mode(fd)? == libc::O_WRONLY {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("fd {} is opened write-only, can't be used as a pipe reader", fd),
+            ));
+        }
+        Ok(Self::from_raw_fd(fd))
+    }
+
+    /// the `File` underneath, for APIs which want `&File`
+    pub fn as_file(&self) -> &File {
+        &self.file
+    }
+
+    /// `as_file` for APIs which want `&mut File`, e.g. `io::copy(reader.by_ref(), ...)`
+    pub fn by_ref(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /**
+    how many bytes a `read` returns right now without blocking (`FIONREAD`)
+
+    only a snapshot, a writer may add more right after, so size the buffer with it but don't expect EOF past it
+    */
+    pub fn bytes_available(&self) -> io::Result<usize> {
+        fionread(self.as_raw_fd())
+    }
+
+    /**
+    poll until a `read` won't block, `false` if `timeout` passed first and `None` waits forever
+
+    a writer hanging up also counts as readable since the next read returns EOF right away
+    */
+    pub fn wait_readable(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        Ok(poll_fd(self.as_raw_fd(), libc::POLLIN, timeout)? != 0)
+    }
+
+    /**
+    poll the fd for `interest` and return the raw `revents`, empty once `timeout` passed (`None` waits forever)
+
+    tells apart data ready (`IN`), every writer gone (`HUP`, after the remaining data also without `IN`) and a
+    closed fd (`NVAL`)
+    */
+    #[cfg(feature = "poll")]
+    pub fn poll_events(&self, interest: PollFlags, timeout: Option<Duration>) -> io::Result<PollFlags> {
+        Ok(PollFlags::from_bits(poll_fd(self.as_raw_fd(), interest.bits(), timeout)?))
+    }
+
+    /// one `read` which gives up with `TimedOut` if nothing arrives in time, `Ok(0)` means the writer closed (EOF)
+    pub fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        self.read_timeout_on(buf, timeout, &SystemClock)
+    }
+
+    /// `read_timeout` measuring the deadline on `clock`
+    #[cfg(feature = "clock")]
+    pub fn read_timeout_with_clock(&mut self, buf: &mut [u8], timeout: Duration, clock: &dyn Clock) -> io::Result<usize> {
+        self.read_timeout_on(buf, timeout, clock)
+    }
+
+    fn read_timeout_on(&mut self, buf: &mut [u8], timeout: Duration, clock: &dyn Clock) -> io::Result<usize> {
+        let deadline = clock.now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(clock.now());
+            if clock.poll_fd(self.as_raw_fd(), libc::POLLIN, Some(remaining))? != 0 {
+                break;
+            }
+            if clock.now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("nothing to read within {:?}", timeout),
+                ));
+            }
+        }
+        loop {
+            match self.file.read(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                res => return res,
+            }
+        }
+    }
+
+    /**
+    `read_to_end` with both a deadline and a size cap
+
+    - writer closed (EOF): `Ok(())`
+    - more than `max_bytes` arrived: `InvalidData`, `out` holds the first `max_bytes` bytes
+    - deadline passed: `TimedOut`, `out` keeps whatever arrived so far
+    */
+    pub fn read_to_end_timeout(&mut self, out: &mut Vec<u8>, max_bytes: usize, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut received = 0;
+        let mut buf = [0u8; 4096];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) || !self.wait_readable(Some(remaining))? {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("no EOF from pipe writer within {:?}", timeout),
+                ));
+            }
+            // ask for one byte past the cap, so a stream of exactly max_bytes still ends cleanly
+            let want = buf.len().min((max_bytes - received).saturating_add(1));
+            let n = match self.file.read(&mut buf[..want]) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                return Ok(());
+            }
+            let keep = n.min(max_bytes - received);
+            out.extend_from_slice(&buf[..keep]);
+            received += keep;
+            if keep < n {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("pipe stream is larger than max_bytes {}", max_bytes),
+                ));
+            }
+        }
+    }
+
+    /**
+    pump everything up to EOF into `dst` through a `buf_size` buffer, returns the bytes copied
+
+    like `std::io::copy`, `EINTR` on the pipe is retried and short writes on `dst` are completed with `write_all`
+    */
+    pub fn copy_to<W: Write>(&mut self, dst: &mut W, buf_size: usize) -> io::Result<u64> {
+        let mut buf = vec![0u8; buf_size.max(1)];
+        let mut copied = 0u64;
+        loop {
+            let n = match self.file.read(&mut buf) {
+                Ok(0) => return Ok(copied),
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            dst.write_all(&buf[..n])?;
+            copied += n as u64;
+        }
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+
+    /**
+    fill `bufs` in order with one readv(2), e.g. a frame header and its body
+
+    like `read` it returns as soon as some bytes are there, which may leave later buffers (or the end of an earlier one)
+    unfilled: the count says where the data stops
+    */
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut]) -> io::Result<usize> {
+        self.file.read_vectored(bufs)
+    }
+}
+
+impl AsRawFd for PipeReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for PipeReader {
+    fn into_raw_fd(self) -> RawFd {
+        self.file.into_raw_fd()
+    }
+}
+
+impl FromRawFd for PipeReader {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        PipeReader::from_file(File::from_raw_fd(fd))
+    }
+}
+
+/**
+a `PipeReader` behind a read buffer, for reading lines and delimited records, see `PipeReader::buffered`
+
+it reads ahead in big chunks, so it has no raw fd of its own: a selector, `poll` or `splice` on the fd wouldn't see
+the bytes sitting in the buffer. `into_parts` gives the reader back together with them
+*/
+#[cfg(feature = "buffered")]
+#[derive(Debug)]
+pub struct BufPipeReader {
+    inner: BufReader<PipeReader>,
+}
+
+#[cfg(feature = "buffered")]
+impl PipeReader {
+    /// wrap into a `BufPipeReader` for `read_line`, `read_until` and `BufRead`
+    pub fn buffered(self) -> BufPipeReader {
+        BufPipeReader { inner: BufReader::new(self) }
+    }
+}
+
+#[cfg(feature = "buffered")]
+impl BufPipeReader {
+    pub fn get_ref(&self) -> &PipeReader {
+        self.inner.get_ref()
+    }
+
+    /// the plain reader and the bytes already read from the fd but not handed out yet
+    pub fn into_parts(self) -> (PipeReader, Vec<u8>) {
+        let rest = self.inner.buffer().to_vec();
+        (self.inner.into_inner(), rest)
+    }
+
+    /// `PipeReader::bytes_available` plus what is buffered here
+    pub fn bytes_available(&self) -> io::Result<usize> {
+        Ok(self.inner.buffer().len() + self.get_ref().bytes_available()?)
+    }
+
+    /// `PipeReader::wait_readable`, right away while the buffer isn't empty
+    pub fn wait_readable(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        if !self.inner.buffer().is_empty() {
+            return Ok(true);
+        }
+        self.get_ref().wait_readable(timeout)
+    }
+
+    /**
+    read up to and including the next `delim` byte into `out`, returns the bytes read
+
+    same as `BufRead::read_until`: `Ok(0)` only at EOF (every writer closed), the last record before EOF may
+    come without a trailing `delim`. Whatever was read past the delimiter stays buffered for the next read
+    */
+    pub fn read_until(&mut self, delim: u8, out: &mut Vec<u8>) -> io::Result<usize> {
+        self.inner.read_until(delim, out)
+    }
+
+    /**
+    read one line including its `\n` and append it to `out`, returns the bytes taken from the pipe, `Ok(0)` at EOF
+
+    writes terminated with `\0` like `b"hello\n\0"` come out as `"hello\n"`: one NUL right after the newline is
+    consumed with the line, as is one at the end of a last line without newline. Any other NUL is data. Other bytes
+    must be UTF-8, else `InvalidData` and `out` is left as it was
+    */
+    pub fn read_line(&mut self, out: &mut String) -> io::Result<usize> {
+        let mut line = Vec::new();
+        let mut n = self.inner.read_until(b'\n', &mut line)?;
+        if line.ends_with(b"\n") && self.nul_follows()? {
+            self.inner.consume(1);
+            n += 1;
+        }
+        let end = if line.ends_with(b"\0") { line.len() - 1 } else { line.len() };
+        let text = std::str::from_utf8(&line[..end])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("line from pipe is not UTF-8: {}", e)))?;
+        out.push_str(text);
+        Ok(n)
+    }
+
+    /**
+    whether the next byte is a NUL, without blocking for it
+
+    a small write like `b"hello\n\0"` lands in the pipe whole, so a terminator missing from the buffer is either
+    already in the pipe or wasn't sent with the line
+    */
+    fn nul_follows(&mut self) -> io::Result<bool> {
+        if self.inner.buffer().is_empty() && self.get_ref().bytes_available()? == 0 {
+            return Ok(false);
+        }
+        Ok(self.inner.fill_buf()?.first() == Some(&0))
+    }
+}
+
+#[cfg(feature = "buffered")]
+impl Read for BufPipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(feature = "buffered")]
+impl BufRead for BufPipeReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt)
+    }
+}
+
+#[test]
+fn test_wait_readable() {
+    let (pipe, reader, mut writer) = ::test_pair("wait_readable");
+    assert!(!reader.wait_readable(Some(Duration::from_millis(20))).unwrap());
+    writer.write_all(b"x").unwrap();
+    assert!(reader.wait_readable(Some(Duration::from_millis(20))).unwrap());
+    assert!(reader.wait_readable(None).unwrap());
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_bytes_available() {
+    let (pipe, mut reader, mut writer) = ::test_pair("bytes_available");
+    assert_eq!(reader.bytes_available().unwrap(), 0);
+    writer.write_all(&[1u8; 42]).unwrap();
+    assert_eq!(reader.bytes_available().unwrap(), 42);
+    let mut buf = vec![0u8; reader.bytes_available().unwrap()];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(reader.bytes_available().unwrap(), 0);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_by_ref_with_io_copy() {
+    use std::os::unix::fs::FileTypeExt;
+
+    let (pipe, mut reader, mut writer) = ::test_pair("by_ref");
+    let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+    let producer = std::thread::spawn(move || writer.write_all(&data).map(|_| data));
+    let mut out = Vec::new();
+    assert_eq!(io::copy(reader.by_ref(), &mut out).unwrap(), 100_000);
+    assert_eq!(out, producer.join().unwrap().unwrap());
+    assert!(reader.as_file().metadata().unwrap().file_type().is_fifo());
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_small_reads_leave_the_rest_on_the_fd() {
+    let (pipe, mut reader, mut writer) = ::test_pair("unbuffered");
+    writer.write_all(b"head rest").unwrap();
+    let mut head = [0u8; 5];
+    reader.read_exact(&mut head).unwrap();
+    // nothing was read ahead, poll and a read on the raw fd still see the rest
+    assert!(reader.wait_readable(Some(Duration::from_secs(0))).unwrap());
+    let mut rest = [0u8; 16];
+    let n = reader.as_file().read(&mut rest).unwrap();
+    assert_eq!(&rest[..n], b"rest");
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_copy_to_vec() {
+    let (pipe, mut reader, mut writer) = ::test_pair("copy_to");
+    let payload: Vec<u8> = (0..200_000u32).map(|i| (i % 253) as u8).collect();
+    let producer = {
+        let payload = payload.clone();
+        std::thread::spawn(move || {
+            for chunk in payload.chunks(10_000) {
+                writer.write_all(chunk).unwrap();
+            }
+        })
+    };
+    let mut copied = Vec::new();
+    assert_eq!(reader.copy_to(&mut copied, 4096).unwrap(), payload.len() as u64);
+    producer.join().unwrap();
+    assert_eq!(copied, payload);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_read_to_end_timeout_eof() {
+    let (pipe, mut reader, mut writer) = ::test_pair("read_to_end_eof");
+    writer.write_all(b"hello").unwrap();
+    drop(writer);
+    let mut out = Vec::new();
+    reader.read_to_end_timeout(&mut out, 1024, Duration::from_secs(5)).unwrap();
+    assert_eq!(out, b"hello");
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_read_to_end_timeout_size_cap() {
+    let (pipe, mut reader, mut writer) = ::test_pair("read_to_end_cap");
+    writer.write_all(&[b'x'; 100]).unwrap();
+    drop(writer);
+    let mut out = Vec::new();
+    let err = reader.read_to_end_timeout(&mut out, 10, Duration::from_secs(5)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(out.len(), 10);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_read_to_end_timeout_deadline() {
+    let (pipe, mut reader, mut writer) = ::test_pair("read_to_end_deadline");
+    writer.write_all(b"abc").unwrap();
+    let mut out = Vec::new();
+    let err = reader.read_to_end_timeout(&mut out, 1024, Duration::from_millis(50)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(out, b"abc");
+    drop(writer);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_read_to_end_timeout_unlimited() {
+    let (pipe, mut reader, mut writer) = ::test_pair("read_to_end_unlimited");
+    writer.write_all(b"no cap").unwrap();
+    drop(writer);
+    let mut out = Vec::new();
+    reader.read_to_end_timeout(&mut out, usize::MAX, Duration::from_secs(5)).unwrap();
+    assert_eq!(out, b"no cap");
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[cfg(feature = "buffered")]
+#[test]
+fn test_read_until_nul_records() {
+    let (pipe, reader, mut writer) = ::test_pair("read_until");
+    let mut reader = reader.buffered();
+    writer.write_all(b"hello\n\0world\0\0tail").unwrap();
+    drop(writer);
+    let mut records = Vec::new();
+    loop {
+        let mut record = Vec::new();
+        match reader.read_until(b'\0', &mut record).unwrap() {
+            0 => break,
+            n => assert_eq!(n, record.len()),
+        }
+        records.push(record);
+    }
+    let expected: Vec<&[u8]> = vec![b"hello\n\0", b"world\0", b"\0", b"tail"];
+    assert_eq!(records, expected);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[cfg(feature = "buffered")]
+#[test]
+fn test_read_line_trims_nul_terminator() {
+    let (pipe, reader, mut writer) = ::test_pair("read_line");
+    let mut reader = reader.buffered();
+    writer.write_all(b"hello\n\0").unwrap();
+    writer.write_all(b"\xff\n").unwrap();
+    drop(writer);
+    let mut line = String::new();
+    assert_eq!(reader.read_line(&mut line).unwrap(), 7);
+    assert_eq!(line, "hello\n");
+    assert_eq!(reader.read_line(&mut line).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    assert_eq!(line, "hello\n");
+    assert_eq!(reader.read_line(&mut line).unwrap(), 0);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[cfg(feature = "buffered")]
+#[test]
+fn test_read_line_keeps_leading_nul() {
+    let (pipe, reader, mut writer) = ::test_pair("read_line_leading_nul");
+    let mut reader = reader.buffered();
+    writer.write_all(b"\0data\n\0").unwrap();
+    writer.write_all(b"\0\0more\n").unwrap();
+    drop(writer);
+    let mut line = String::new();
+    assert_eq!(reader.read_line(&mut line).unwrap(), 7);
+    assert_eq!(line, "\0data\n");
+    line.clear();
+    assert_eq!(reader.read_line(&mut line).unwrap(), 7);
+    assert_eq!(line, "\0\0more\n");
+    assert_eq!(reader.read_line(&mut line).unwrap(), 0);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[cfg(feature = "buffered")]
+#[test]
+fn test_buffered_into_parts_keeps_read_ahead() {
+    let (pipe, reader, mut writer) = ::test_pair("buffered_into_parts");
+    writer.write_all(b"line\nrest").unwrap();
+    let mut reader = reader.buffered();
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "line\n");
+    assert_eq!(reader.bytes_available().unwrap(), 4);
+    let (reader, rest) = reader.into_parts();
+    assert_eq!(rest, b"rest");
+    assert!(!reader.wait_readable(Some(Duration::from_millis(0))).unwrap());
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_read_vectored_header_and_body() {
+    let (pipe, mut reader, mut writer) = ::test_pair("read_vectored");
+    writer.write_all(b"HEADbody!").unwrap();
+    let mut header = [0u8; 4];
+    let mut body = [0u8; 5];
+    let n = reader
+        .read_vectored(&mut [IoSliceMut::new(&mut header), IoSliceMut::new(&mut body)])
+        .unwrap();
+    assert_eq!(n, 9);
+    assert_eq!(&header, b"HEAD");
+    assert_eq!(&body, b"body!");
+
+    // fewer bytes than the buffers want: the count tells how far they got
+    writer.write_all(b"HEADbo").unwrap();
+    drop(writer);
+    let mut header = [0u8; 4];
+    let mut body = [0u8; 5];
+    let n = reader
+        .read_vectored(&mut [IoSliceMut::new(&mut header), IoSliceMut::new(&mut body)])
+        .unwrap();
+    assert_eq!(n, 6);
+    assert_eq!(&header, b"HEAD");
+    assert_eq!(&body[..n - header.len()], b"bo");
+    assert_eq!(reader.read_vectored(&mut [IoSliceMut::new(&mut header)]).unwrap(), 0);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
@@ -0,0 +1,103 @@
+use std::io::{self, Write};
+
+use core::pipe::NamedPipe;
+use core::retry::RetryPolicy;
+use core::writer::PipeWriter;
+
+/**
+writer which survives the reader going away
+
+the FIFO is (re)opened lazily via `NamedPipe::connect_with_retry`, a write failing with `BrokenPipe` drops the fd, reconnects and is tried once more.
+Bytes the old reader never consumed are lost with it
+*/
+#[derive(Debug)]
+pub struct ReconnectingWriter {
+    pipe: NamedPipe,
+    policy: RetryPolicy,
+    writer: Option<PipeWriter>,
+}
+
+impl ReconnectingWriter {
+    pub fn new(pipe: NamedPipe, policy: RetryPolicy) -> Self {
+        ReconnectingWriter {
+            pipe,
+            policy,
+            writer: None,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    fn writer(&mut self) -> io::Result<&mut PipeWriter> {
+        if self.writer.is_none() {
+            self.writer = Some(self.pipe.connect_with_retry(&self.policy)?);
+        }
+        Ok(self.writer.as_mut().unwrap())
+    }
+}
+
+impl Write for ReconnectingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.writer()?.write(buf) {
+            Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                self.writer = None;
+                self.writer()?.write(buf)
+            }
+            res => res,
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.writer {
+            Some(ref mut writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_reconnecting_writer_honors_policy() {
+    use std::io::Read;
+    use std::time::{Duration, Instant};
+
+    let pipe = ::test_pipe("reconnecting_writer");
+    let policy = RetryPolicy {
+        max_attempts: 20,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(20),
+        multiplier: 2.0,
+        ..RetryPolicy::default()
+    };
+    let mut writer = ReconnectingWriter::new(pipe.clone(), policy);
+
+    // reader attaches late, the first write has to back off until then
+    let spawn_reader = |delay: Duration| {
+        let pipe = pipe.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(delay);
+            let mut reader = pipe.open_reader().unwrap();
+            let mut buf = [0u8; 5];
+            reader.read_exact(&mut buf).unwrap();
+            buf
+        })
+    };
+    let first = spawn_reader(Duration::from_millis(50));
+    let start = Instant::now();
+    writer.write_all(b"hello").unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(&first.join().unwrap(), b"hello");
+
+    // the reader is gone now, next write hits EPIPE and reconnects to the restarted reader
+    let second = spawn_reader(Duration::from_millis(30));
+    writer.write_all(b"world").unwrap();
+    assert_eq!(&second.join().unwrap(), b"world");
+
+    // a single shot policy gives up right away when nobody is reading
+    let mut writer = ReconnectingWriter::new(pipe.clone(), RetryPolicy::none());
+    let err = writer.write_all(b"lost").unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENXIO));
+    assert!(!writer.is_connected());
+    std::fs::remove_file(pipe.path()).unwrap();
+}
@@ -0,0 +1,60 @@
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use libc;
+
+use core::sys::{cvt, fionread};
+use core::writer::{PipeWriter, WriteStats};
+
+/// one snapshot of a writer for monitoring, fields the platform can't tell are `None`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipeReport {
+    /// pipe buffer size, Linux only (`F_GETPIPE_SZ`)
+    pub capacity: Option<usize>,
+    /// bytes written but not read yet, `ioctl(FIONREAD)` which Linux answers on either end of a pipe
+    pub buffered: Option<usize>,
+    pub nonblocking: bool,
+    pub stats: WriteStats,
+}
+
+impl PipeWriter {
+    pub fn report(&self) -> io::Result<PipeReport> {
+        #[cfg(target_os = "linux")]
+        let capacity = self.capacity().ok();
+        #[cfg(not(target_os = "linux"))]
+        let capacity = None;
+        let buffered = fionread(self.as_raw_fd()).ok();
+        let flags = cvt(unsafe { libc::fcntl(self.as_raw_fd(), libc::F_GETFL) })?;
+        Ok(PipeReport {
+            capacity,
+            buffered,
+            nonblocking: flags & libc::O_NONBLOCK != 0,
+            stats: self.stats(),
+        })
+    }
+}
+
+#[test]
+fn test_report_counts_unread_bytes() {
+    use std::io::Write;
+
+    let (pipe, _reader, mut writer) = ::test_pair("report");
+    writer.write_all(b"hello").unwrap();
+    writer.write_all(b" world").unwrap();
+    let report = writer.report().unwrap();
+    #[cfg(target_os = "linux")]
+    {
+        assert_eq!(report.buffered, Some(11));
+        assert!(report.capacity.unwrap() >= 4096);
+    }
+    assert!(!report.nonblocking);
+    assert_eq!(
+        report.stats,
+        WriteStats {
+            writes: 2,
+            bytes: 11,
+            errors: 0
+        }
+    );
+    std::fs::remove_file(pipe.path()).unwrap();
+}
@@ -0,0 +1,124 @@
+use std::io;
+use std::time::Duration;
+
+use core::clock::{Clock, SystemClock};
+use core::spin::SpinPolicy;
+
+/// exponential backoff shared by every helper that retries, e.g. `NamedPipe::connect_with_retry` and `ReconnectingWriter`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// total tries including the first one, 0 behaves like 1
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// how each backoff is waited out, see `SpinPolicy::wait`
+    pub spin: SpinPolicy,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            multiplier: 2.0,
+            spin: SpinPolicy::default(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// single shot, no retry no sleep
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            initial_backoff: Duration::from_secs(0),
+            max_backoff: Duration::from_secs(0),
+            multiplier: 1.0,
+            spin: SpinPolicy::default(),
+        }
+    }
+
+    /// delay to sleep after the failed `attempt` (0-based): initial_backoff * multiplier^attempt, capped at max_backoff
+    pub fn next_delay(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.powi(attempt.min(i32::MAX as u32) as i32);
+        let delay = self.initial_backoff.as_secs_f64() * factor;
+        if !delay.is_finite() || delay >= self.max_backoff.as_secs_f64() {
+            return self.max_backoff;
+        }
+        Duration::from_secs_f64(delay.max(0.0))
+    }
+
+    /**
+    run `op` until it succeeds, `should_retry` rejects the error, or the attempts run out
+
+    the last error is returned as is
+    */
+    pub fn retry<T, F, P>(&self, op: F, should_retry: P) -> io::Result<T>
+    where
+        F: FnMut() -> io::Result<T>,
+        P: Fn(&io::Error) -> bool,
+    {
+        self.retry_on(op, should_retry, &SystemClock)
+    }
+
+    /// `retry` waiting on `clock`
+    #[cfg(feature = "clock")]
+    pub fn retry_with_clock<T, F, P>(&self, op: F, should_retry: P, clock: &dyn Clock) -> io::Result<T>
+    where
+        F: FnMut() -> io::Result<T>,
+        P: Fn(&io::Error) -> bool,
+    {
+        self.retry_on(op, should_retry, clock)
+    }
+
+    fn retry_on<T, F, P>(&self, mut op: F, should_retry: P, clock: &dyn Clock) -> io::Result<T>
+    where
+        F: FnMut() -> io::Result<T>,
+        P: Fn(&io::Error) -> bool,
+    {
+        let max_attempts = self.max_attempts.max(1);
+        let mut attempt = 0;
+        loop {
+            match op() {
+                Ok(val) => return Ok(val),
+                Err(e) => {
+                    if attempt + 1 >= max_attempts || !should_retry(&e) {
+                        return Err(e);
+                    }
+                    clock.spin_wait(self.next_delay(attempt), self.spin);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_backoff_schedule() {
+    let policy = RetryPolicy {
+        max_attempts: 10,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(100),
+        multiplier: 2.0,
+        ..RetryPolicy::default()
+    };
+    let delays: Vec<_> = (0..6).map(|attempt| policy.next_delay(attempt)).collect();
+    let expected: Vec<_> = [10, 20, 40, 80, 100, 100].iter().map(|&ms| Duration::from_millis(ms)).collect();
+    assert_eq!(delays, expected);
+}
+
+#[test]
+fn test_retry_none_is_single_shot() {
+    let mut calls = 0;
+    let res: io::Result<()> = RetryPolicy::none().retry(
+        || {
+            calls += 1;
+            Err(io::Error::from_raw_os_error(libc::ENXIO))
+        },
+        |_| true,
+    );
+    assert!(res.is_err());
+    assert_eq!(calls, 1);
+}
@@ -0,0 +1,114 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+
+use libc;
+
+use core::pipe::NamedPipe;
+use core::sys::{cvt, path_to_cstring};
+
+fn insecure(what: &Path, why: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, format!("refusing {}: {}", what.display(), why))
+}
+
+impl NamedPipe {
+    /**
+    create (or reuse) the FIFO `name` inside a private directory `dir`, for paths under world writable places like /tmp
+
+    `dir` is created with mode 0700 if missing, an existing one must be a real directory (not a symlink) owned by the
+    effective uid and closed to group and others. The FIFO is created relative to that opened directory and checked with
+    `lstat` semantics: whatever an attacker planted at the name (a symlink, a file, someone else's FIFO) is refused with
+    `PermissionDenied` or `AlreadyExists` instead of being followed
+    */
+    pub fn create_secure<P: AsRef<Path>>(dir: P, name: &str, mode: u32) -> io::Result<NamedPipe> {
+        let dir = dir.as_ref();
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} is not a plain file name", name),
+            ));
+        }
+        let path = dir.join(name);
+        let dir_c = path_to_cstring(dir)?;
+        match cvt(unsafe { libc::mkdir(dir_c.as_ptr(), 0o700) }) {
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            res => {
+                res?;
+            }
+        }
+        let dir_fd = match cvt(unsafe {
+            libc::open(dir_c.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC)
+        }) {
+            Ok(fd) => unsafe { File::from_raw_fd(fd) },
+            Err(ref e) if e.raw_os_error() == Some(libc::ELOOP) => return Err(insecure(dir, "directory is a symlink")),
+            Err(e) => return Err(e),
+        };
+        let euid = unsafe { libc::geteuid() };
+
+        let dir_stat = dir_fd.metadata()?;
+        if dir_stat.uid() != euid {
+            return Err(insecure(dir, "directory is owned by another user"));
+        }
+        if dir_stat.mode() & 0o077 != 0 {
+            return Err(insecure(dir, "directory is accessible by group or others"));
+        }
+
+        let name_c = path_to_cstring(Path::new(name))?;
+        match cvt(unsafe { libc::mkfifoat(dir_fd.as_raw_fd(), name_c.as_ptr(), mode as libc::mode_t) }) {
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            res => {
+                res?;
+            }
+        }
+        // mkfifoat never follows a symlink at `name`, it fails with EEXIST, so look at what's there without following either
+        let mut file_stat = unsafe { std::mem::zeroed::<libc::stat>() };
+        cvt(unsafe {
+            libc::fstatat(dir_fd.as_raw_fd(), name_c.as_ptr(), &mut file_stat, libc::AT_SYMLINK_NOFOLLOW)
+        })?;
+        match file_stat.st_mode & libc::S_IFMT {
+            libc::S_IFIFO => {}
+            libc::S_IFLNK => return Err(insecure(&path, "it is a symlink")),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a FIFO", path.display()),
+                ))
+            }
+        }
+        if file_stat.st_uid != euid {
+            return Err(insecure(&path, "FIFO is owned by another user"));
+        }
+        Ok(NamedPipe::new(path))
+    }
+}
+
+#[test]
+fn test_create_secure_refuses_symlink() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("rust_pipe_{}_secure", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let pipe = NamedPipe::create_secure(&dir, "fifo", 0o600).unwrap();
+    assert!(pipe.is_fifo().unwrap());
+    assert_eq!(std::fs::metadata(&dir).unwrap().permissions().mode() & 0o777, 0o700);
+    // reusing our own FIFO is fine
+    assert_eq!(NamedPipe::create_secure(&dir, "fifo", 0o600).unwrap(), pipe);
+
+    let target = dir.join("target");
+    std::fs::write(&target, b"").unwrap();
+    std::os::unix::fs::symlink(&target, dir.join("planted")).unwrap();
+    let err = NamedPipe::create_secure(&dir, "planted", 0o600).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    assert!(err.to_string().contains("symlink"), "{}", err);
+    // the symlink target wasn't touched
+    assert!(std::fs::metadata(&target).unwrap().is_file());
+
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+    assert_eq!(
+        NamedPipe::create_secure(&dir, "fifo", 0o600).unwrap_err().kind(),
+        io::ErrorKind::PermissionDenied
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
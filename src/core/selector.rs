@@ -0,0 +1,300 @@
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use libc;
+
+use core::sys::{cvt, poll_timeout_ms, read_fd};
+
+/// with `Backend::Auto` the selector moves from poll to epoll above this many registered pipes
+pub const EPOLL_THRESHOLD: usize = 64;
+/// most bytes one `select_read` returns
+const SELECT_READ_MAX: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// poll(2), O(n) per `select`, portable
+    Poll,
+    /// epoll(7), level triggered so it reports the same readiness as poll
+    #[cfg(target_os = "linux")]
+    Epoll,
+    /// poll for a handful of pipes, epoll (where available) past `EPOLL_THRESHOLD`
+    Auto,
+}
+
+/**
+wait on many pipe readers at once, `select` returns the indices `register` handed out
+
+a reader whose writer hung up counts as ready too, its next read returns EOF
+*/
+#[derive(Debug)]
+pub struct PipeSelector {
+    backend: Backend,
+    /// index -> fd, -1 once deregistered (poll skips negative fds)
+    fds: Vec<libc::pollfd>,
+    registered: usize,
+    #[cfg(target_os = "linux")]
+    epoll: Option<RawFd>,
+}
+
+impl Default for PipeSelector {
+    fn default() -> Self {
+        PipeSelector::new()
+    }
+}
+
+impl PipeSelector {
+    pub fn new() -> Self {
+        PipeSelector::with_backend(Backend::Auto)
+    }
+
+    pub fn with_backend(backend: Backend) -> Self {
+        PipeSelector {
+            backend,
+            fds: Vec::new(),
+            registered: 0,
+            #[cfg(target_os = "linux")]
+            epoll: None,
+        }
+    }
+
+    /// registered pipes
+    pub fn len(&self) -> usize {
+        self.registered
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.registered == 0
+    }
+
+    /// index of the reader in `select` results, the selector doesn't own it so it has to outlive the registration
+    pub fn register<T: AsRawFd>(&mut self, reader: &T) -> io::Result<usize> {
+        let index = self.fds.len();
+        let fd = reader.as_raw_fd();
+        self.fds.push(libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        });
+        self.registered += 1;
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(epoll) = self.epoll {
+                if let Err(e) = epoll_add(epoll, fd, index) {
+                    self.fds[index].fd = -1;
+                    self.registered -= 1;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(index)
+    }
+
+    /// `index` was handed out by `register` and neither deregistered nor hung up in `select_read` since
+    pub fn is_registered(&self, index: usize) -> bool {
+        self.fds.get(index).is_some_and(|pollfd| pollfd.fd >= 0)
+    }
+
+    /// stop watching `index`, the other indices stay valid
+    pub fn deregister(&mut self, index: usize) -> io::Result<()> {
+        let fd = match self.fds.get(index) {
+            Some(pollfd) if pollfd.fd >= 0 => pollfd.fd,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("index {} is not registered", index),
+                ))
+            }
+        };
+        self.fds[index].fd = -1;
+        self.registered -= 1;
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(epoll) = self.epoll {
+                cvt(unsafe { libc::epoll_ctl(epoll, libc::EPOLL_CTL_DEL, fd, std::ptr::null_mut()) })?;
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = fd;
+        Ok(())
+    }
+
+    /// indices of the ready readers in ascending order, empty on timeout, `None` waits forever
+    pub fn select(&mut self, timeout: Option<Duration>) -> io::Result<Vec<usize>> {
+        #[cfg(target_os = "linux")]
+        {
+            if self.use_epoll() {
+                return self.select_epoll(timeout);
+            }
+        }
+        self.select_poll(timeout)
+    }
+
+    /**
+    wait like `select`, then one read of at most 64K from the lowest ready index, `Ok(None)` on timeout
+
+    an empty `Vec` means every writer of that pipe hung up (EOF), the index is deregistered then, so it isn't reported
+    again and `is_registered` tells it apart afterwards. The read goes straight to the fd, so don't register a reader
+    which was turned into a `BufPipeReader`, bytes it read ahead aren't seen
+    */
+    pub fn select_read(&mut self, timeout: Option<Duration>) -> io::Result<Option<(usize, Vec<u8>)>> {
+        let index = match self.select(timeout)?.first() {
+            Some(&index) => index,
+            None => return Ok(None),
+        };
+        let fd = self.fds[index].fd;
+        let mut buf = vec![0u8; SELECT_READ_MAX];
+        let n = read_fd(fd, &mut buf)?;
+        buf.truncate(n);
+        if n == 0 {
+            self.deregister(index)?;
+        }
+        Ok(Some((index, buf)))
+    }
+
+    fn select_poll(&mut self, timeout: Option<Duration>) -> io::Result<Vec<usize>> {
+        for pollfd in &mut self.fds {
+            pollfd.revents = 0;
+        }
+        let timeout_ms = poll_timeout_ms(timeout);
+        loop {
+            match cvt(unsafe { libc::poll(self.fds.as_mut_ptr(), self.fds.len() as libc::nfds_t, timeout_ms) }) {
+                Ok(_) => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(self
+            .fds
+            .iter()
+            .enumerate()
+            .filter(|&(_, pollfd)| pollfd.fd >= 0 && pollfd.revents != 0)
+            .map(|(index, _)| index)
+            .collect())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn use_epoll(&self) -> bool {
+        match self.backend {
+            Backend::Epoll => true,
+            Backend::Auto => self.epoll.is_some() || self.registered > EPOLL_THRESHOLD,
+            Backend::Poll => false,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn select_epoll(&mut self, timeout: Option<Duration>) -> io::Result<Vec<usize>> {
+        let epoll = match self.epoll {
+            Some(epoll) => epoll,
+            None => {
+                let epoll = cvt(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+                self.epoll = Some(epoll);
+                for (index, pollfd) in self.fds.iter().enumerate() {
+                    if pollfd.fd >= 0 {
+                        epoll_add(epoll, pollfd.fd, index)?;
+                    }
+                }
+                epoll
+            }
+        };
+        let mut events = vec![libc::epoll_event { events: 0, u64: 0 }; self.registered.max(1)];
+        let timeout_ms = poll_timeout_ms(timeout);
+        let n = loop {
+            match cvt(unsafe { libc::epoll_wait(epoll, events.as_mut_ptr(), events.len() as libc::c_int, timeout_ms) }) {
+                Ok(n) => break n as usize,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        };
+        let mut ready: Vec<usize> = events[..n].iter().map(|event| event.u64 as usize).collect();
+        ready.sort_unstable();
+        Ok(ready)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn epoll_add(epoll: RawFd, fd: RawFd, index: usize) -> io::Result<()> {
+    // no EPOLLET: level triggered like poll, a pipe stays ready until it's drained
+    let mut event = libc::epoll_event {
+        events: libc::EPOLLIN as u32,
+        u64: index as u64,
+    };
+    cvt(unsafe { libc::epoll_ctl(epoll, libc::EPOLL_CTL_ADD, fd, &mut event) })?;
+    Ok(())
+}
+
+impl Drop for PipeSelector {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(epoll) = self.epoll.take() {
+                unsafe { libc::close(epoll) };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn check_backend(backend: Backend) {
+    use std::io::Write;
+
+    let mut pipes: Vec<_> = (0..200).map(|_| ::Pipe::anonymous().unwrap()).collect();
+    let mut selector = PipeSelector::with_backend(backend);
+    for (index, (reader, _)) in pipes.iter().enumerate() {
+        assert_eq!(selector.register(reader).unwrap(), index);
+    }
+    assert!(selector.select(Some(Duration::from_millis(10))).unwrap().is_empty());
+
+    let written = [3, 17, 64, 65, 128, 199];
+    for &index in &written {
+        pipes[index].1.write_all(b"x").unwrap();
+    }
+    assert_eq!(selector.select(Some(Duration::from_secs(1))).unwrap(), written.to_vec());
+    // level triggered: still ready while unread
+    assert_eq!(selector.select(Some(Duration::from_secs(1))).unwrap(), written.to_vec());
+    selector.deregister(64).unwrap();
+    assert_eq!(selector.select(Some(Duration::from_secs(1))).unwrap(), vec![3, 17, 65, 128, 199]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_selector_epoll_backend() {
+    check_backend(Backend::Epoll);
+}
+
+#[test]
+fn test_selector_poll_backend() {
+    check_backend(Backend::Poll);
+}
+
+#[test]
+fn test_selector_auto_backend() {
+    check_backend(Backend::Auto);
+}
+
+#[test]
+fn test_select_read_whichever_is_ready() {
+    use std::io::Write;
+
+    let (reader_a, mut writer_a) = ::Pipe::anonymous().unwrap();
+    let (reader_b, mut writer_b) = ::Pipe::anonymous().unwrap();
+    let mut selector = PipeSelector::new();
+    let a = selector.register(&reader_a).unwrap();
+    let b = selector.register(&reader_b).unwrap();
+    assert_eq!(selector.select_read(Some(Duration::from_millis(10))).unwrap(), None);
+
+    let producer = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        writer_b.write_all(b"from b").unwrap();
+    });
+    assert_eq!(selector.select_read(None).unwrap(), Some((b, b"from b".to_vec())));
+    producer.join().unwrap();
+    writer_a.write_all(b"from a").unwrap();
+    assert_eq!(selector.select_read(None).unwrap(), Some((a, b"from a".to_vec())));
+
+    // writer_b was dropped with its thread
+    assert_eq!(selector.select_read(None).unwrap(), Some((b, Vec::new())));
+    assert!(!selector.is_registered(b));
+    assert!(selector.is_registered(a));
+    assert_eq!(selector.select_read(Some(Duration::from_millis(10))).unwrap(), None);
+}
@@ -0,0 +1,182 @@
+/*!
+a reader another thread can wake up and stop, for services which otherwise sit in a blocking `read` forever
+
+every read polls the pipe together with a control fd: an eventfd on Linux, a self-pipe elsewhere. Signaling makes the
+control fd readable for good, so the blocked read and every later one fail with `PipeError::Shutdown`
+*/
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+use std::time::Duration;
+
+use libc;
+
+use core::error::PipeError;
+use core::reader::PipeReader;
+use core::sys::{cvt, poll_fd};
+
+/// the control fd, never drained so it stays readable once signaled
+#[derive(Debug)]
+struct Control {
+    /// polled for readability
+    read_fd: RawFd,
+    /// written by `signal`, the same fd as `read_fd` for an eventfd
+    write_fd: RawFd,
+}
+
+impl Control {
+    #[cfg(target_os = "linux")]
+    fn new() -> io::Result<Self> {
+        let fd = cvt(unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) })?;
+        Ok(Control { read_fd: fd, write_fd: fd })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn new() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        cvt(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+        let control = Control {
+            read_fd: fds[0],
+            write_fd: fds[1],
+        };
+        for &fd in &fds {
+            cvt(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+        }
+        // a second signal on a full self-pipe must not block
+        cvt(unsafe { libc::fcntl(control.write_fd, libc::F_SETFL, libc::O_NONBLOCK) })?;
+        Ok(control)
+    }
+
+    fn signal(&self) -> io::Result<()> {
+        let one = 1u64.to_ne_bytes();
+        // eventfd wants exactly 8 bytes, a self-pipe is happy with any of them
+        let len = if self.read_fd == self.write_fd { one.len() } else { 1 };
+        loop {
+            let ret = unsafe { libc::write(self.write_fd, one.as_ptr() as *const libc::c_void, len) };
+            if ret != -1 {
+                return Ok(());
+            }
+            let err = io::Error::last_os_error();
+            match err.kind() {
+                io::ErrorKind::Interrupted => continue,
+                // already readable, which is all a signal has to achieve
+                io::ErrorKind::WouldBlock => return Ok(()),
+                _ => return Err(err),
+            }
+        }
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.read_fd) };
+        if self.write_fd != self.read_fd {
+            unsafe { libc::close(self.write_fd) };
+        }
+    }
+}
+
+/// stops the `ShutdownReader` it came from, cheap to clone and send to other threads
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    control: Arc<Control>,
+}
+
+impl ShutdownHandle {
+    /// wake up a blocked read of the reader with `PipeError::Shutdown`, later reads fail the same way
+    pub fn signal(&self) -> io::Result<()> {
+        self.control.signal()
+    }
+
+    pub fn is_signaled(&self) -> io::Result<bool> {
+        Ok(poll_fd(self.control.read_fd, libc::POLLIN, Some(Duration::from_secs(0)))? != 0)
+    }
+}
+
+/// `PipeReader` whose reads can be interrupted from another thread through a `ShutdownHandle`
+#[derive(Debug)]
+pub struct ShutdownReader {
+    reader: PipeReader,
+    handle: ShutdownHandle,
+}
+
+impl ShutdownReader {
+    pub fn new(reader: PipeReader) -> io::Result<Self> {
+        Ok(ShutdownReader {
+            reader,
+            handle: ShutdownHandle {
+                control: Arc::new(Control::new()?),
+            },
+        })
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.handle.clone()
+    }
+
+    pub fn into_inner(self) -> PipeReader {
+        self.reader
+    }
+
+    /// block until either the pipe is readable (`Ok`) or shutdown was signaled
+    fn wait(&self) -> io::Result<()> {
+        let mut fds = [
+            libc::pollfd {
+                fd: self.reader.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: self.handle.control.read_fd,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        loop {
+            match cvt(unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) }) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+                Ok(_) => break,
+            }
+        }
+        if fds[1].revents != 0 {
+            return Err(PipeError::Shutdown.into());
+        }
+        Ok(())
+    }
+}
+
+impl Read for ShutdownReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.wait()?;
+        self.reader.read(buf)
+    }
+}
+
+#[test]
+fn test_signal_wakes_blocked_read() {
+    use std::time::Instant;
+
+    let (pipe, reader, writer) = ::test_pair("shutdown_reader");
+    let mut reader = ShutdownReader::new(reader).unwrap();
+    let handle = reader.shutdown_handle();
+    assert!(!handle.is_signaled().unwrap());
+    let signaled_at = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        handle.signal().unwrap();
+        handle.signal().unwrap();
+        Instant::now()
+    });
+    let mut out = String::new();
+    let err = reader.read_to_string(&mut out).unwrap_err();
+    let returned_at = Instant::now();
+    match PipeError::downcast(&err) {
+        Some(&PipeError::Shutdown) => {}
+        other => panic!("expected Shutdown, got {:?}", other),
+    }
+    assert!(returned_at - signaled_at.join().unwrap() < Duration::from_secs(1));
+    // once signaled, even a pending EOF is not reported
+    drop(writer);
+    assert!(reader.read(&mut [0u8; 16]).is_err());
+    std::fs::remove_file(pipe.path()).unwrap();
+}
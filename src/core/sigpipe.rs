@@ -0,0 +1,102 @@
+use std::fmt;
+use std::io;
+use std::ptr;
+
+use libc;
+
+use core::sys::cvt;
+
+/**
+restores the SIGPIPE disposition `ignore_sigpipe` replaced once it's dropped
+
+the disposition is process wide: drop guards in the reverse order they were taken, and don't expect other threads
+changing SIGPIPE in between to survive the restore
+*/
+pub struct SigPipeGuard {
+    previous: libc::sigaction,
+}
+
+impl fmt::Debug for SigPipeGuard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SigPipeGuard")
+            .field("previous_handler", &self.previous.sa_sigaction)
+            .field("previous_flags", &self.previous.sa_flags)
+            .finish()
+    }
+}
+
+impl Drop for SigPipeGuard {
+    fn drop(&mut self) {
+        unsafe { libc::sigaction(libc::SIGPIPE, &self.previous, ptr::null_mut()) };
+    }
+}
+
+/// `SIG_IGN` for SIGPIPE, returns the whole previous `sigaction` (handler, mask and flags)
+fn set_sigpipe_ignored() -> io::Result<libc::sigaction> {
+    unsafe {
+        let mut ignore = std::mem::zeroed::<libc::sigaction>();
+        ignore.sa_sigaction = libc::SIG_IGN;
+        libc::sigemptyset(&mut ignore.sa_mask);
+        let mut previous = std::mem::zeroed::<libc::sigaction>();
+        cvt(libc::sigaction(libc::SIGPIPE, &ignore, &mut previous))?;
+        Ok(previous)
+    }
+}
+
+/**
+ignore SIGPIPE so a write to a pipe without reader fails with `BrokenPipe` instead of killing the process,
+until the guard is dropped
+
+for one handle `PipeWriter::set_block_sigpipe` is the gentler option, it leaves the disposition alone
+*/
+pub fn ignore_sigpipe() -> io::Result<SigPipeGuard> {
+    Ok(SigPipeGuard {
+        previous: set_sigpipe_ignored()?,
+    })
+}
+
+/// `ignore_sigpipe` for the rest of the process, for binaries which own their signal setup
+pub fn ignore_sigpipe_permanent() -> io::Result<()> {
+    set_sigpipe_ignored().map(|_| ())
+}
+
+#[test]
+fn test_guard_restores_previous_handler() {
+    use std::os::unix::io::AsRawFd;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static HANDLER_RAN: AtomicBool = AtomicBool::new(false);
+    extern "C" fn on_sigpipe(_: libc::c_int) {
+        HANDLER_RAN.store(true, Ordering::SeqCst);
+    }
+
+    // the disposition is process wide, so the other tests' threads don't get to see the custom handler
+    let pid = unsafe { libc::fork() };
+    assert_ne!(pid, -1);
+    if pid == 0 {
+        let (reader, writer) = ::Pipe::anonymous().unwrap();
+        drop(reader);
+        let write_broken = || unsafe { libc::write(writer.as_raw_fd(), b"x".as_ptr() as *const libc::c_void, 1) };
+        let code = unsafe {
+            let mut custom = std::mem::zeroed::<libc::sigaction>();
+            custom.sa_sigaction = on_sigpipe as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::sigemptyset(&mut custom.sa_mask);
+            libc::sigaction(libc::SIGPIPE, &custom, ptr::null_mut());
+
+            let guard = ignore_sigpipe().unwrap();
+            let ignored = write_broken() == -1 && !HANDLER_RAN.load(Ordering::SeqCst);
+            drop(guard);
+            let restored = write_broken() == -1 && HANDLER_RAN.load(Ordering::SeqCst);
+            match (ignored, restored) {
+                (true, true) => 0,
+                (false, _) => 1,
+                (true, false) => 2,
+            }
+        };
+        unsafe { libc::_exit(code) };
+    }
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status), "child killed by signal {}", libc::WTERMSIG(status));
+    assert_eq!(libc::WEXITSTATUS(status), 0);
+}
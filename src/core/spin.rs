@@ -0,0 +1,139 @@
+#[cfg(feature = "retry")]
+use std::io;
+use std::thread;
+use std::time::Duration;
+#[cfg(feature = "retry")]
+use std::time::Instant;
+
+#[cfg(feature = "retry")]
+use core::pipe::{is_peer_missing, NamedPipe};
+#[cfg(feature = "retry")]
+use core::writer::PipeWriter;
+
+/**
+how a loop waits between tries when there's no fd to `poll` on, e.g. waiting for a FIFO's first reader
+
+`Busy` reacts fastest and burns a whole core, `Sleep` costs next to no CPU but adds up to its duration of latency.
+Used by `connect_spinning`, the backoff of every `RetryPolicy` and a writer retrying writes of 0 bytes
+(`PipeWriter::set_spin_policy`)
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+// without the retry feature nothing can pick anything but the default
+#[cfg_attr(not(feature = "retry"), allow(dead_code))]
+pub enum SpinPolicy {
+    /// retry right away, only a `spin_loop` hint in between
+    Busy,
+    /// give the rest of the time slice to other threads
+    Yield,
+    Sleep(Duration),
+}
+
+impl Default for SpinPolicy {
+    /// a short sleep, an unattended loop shouldn't eat a core
+    fn default() -> Self {
+        SpinPolicy::Sleep(Duration::from_millis(1))
+    }
+}
+
+impl SpinPolicy {
+    pub fn pause(&self) {
+        match *self {
+            SpinPolicy::Busy => std::hint::spin_loop(),
+            SpinPolicy::Yield => thread::yield_now(),
+            SpinPolicy::Sleep(duration) => thread::sleep(duration),
+        }
+    }
+
+    /**
+    run `op` until it succeeds or `should_retry` rejects the error, pausing in between, `TimedOut` after `timeout`
+
+    unlike `RetryPolicy` there's no attempt limit and no backoff, every pause is the same
+    */
+    #[cfg(feature = "retry")]
+    pub fn retry_for<T, F, P>(&self, timeout: Duration, mut op: F, should_retry: P) -> io::Result<T>
+    where
+        F: FnMut() -> io::Result<T>,
+        P: Fn(&io::Error) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match op() {
+                Ok(val) => return Ok(val),
+                Err(e) => {
+                    if !should_retry(&e) {
+                        return Err(e);
+                    }
+                    if Instant::now() >= deadline {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("still failing after {:?}: {}", timeout, e),
+                        ));
+                    }
+                    self.pause();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "retry")]
+impl NamedPipe {
+    /// `try_open_writer` until a reader shows up or `timeout` passes, waiting per `spin` in between
+    pub fn connect_spinning(&self, spin: SpinPolicy, timeout: Duration) -> io::Result<PipeWriter> {
+        spin.retry_for(timeout, || self.try_open_writer(), is_peer_missing)
+    }
+}
+
+/// user plus system CPU time of the calling thread
+#[cfg(all(test, feature = "retry", target_os = "linux"))]
+fn thread_cpu_time() -> Duration {
+    let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+    assert_eq!(unsafe { libc::getrusage(libc::RUSAGE_THREAD, &mut usage) }, 0);
+    let micros = |tv: libc::timeval| tv.tv_sec as u64 * 1_000_000 + tv.tv_usec as u64;
+    Duration::from_micros(micros(usage.ru_utime) + micros(usage.ru_stime))
+}
+
+#[cfg(all(feature = "retry", target_os = "linux"))]
+#[test]
+fn test_sleep_spin_uses_less_cpu_than_busy() {
+    let pipe = ::test_pipe("spin_policy");
+    let window = Duration::from_millis(100);
+    let cpu_while_connecting = |spin: SpinPolicy| {
+        let before = thread_cpu_time();
+        let err = pipe.connect_spinning(spin, window).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        thread_cpu_time() - before
+    };
+    let busy = cpu_while_connecting(SpinPolicy::Busy);
+    let sleep = cpu_while_connecting(SpinPolicy::Sleep(Duration::from_millis(10)));
+    assert!(sleep * 5 < busy, "sleep {:?} busy {:?}", sleep, busy);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[cfg(all(feature = "retry", target_os = "linux"))]
+#[test]
+fn test_retry_backoff_honors_spin_policy() {
+    use core::retry::RetryPolicy;
+
+    let pipe = ::test_pipe("spin_policy_retry");
+    let cpu_while_connecting = |spin: SpinPolicy| {
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(30),
+            multiplier: 1.0,
+            spin,
+            ..RetryPolicy::default()
+        };
+        let before = thread_cpu_time();
+        let start = Instant::now();
+        let err = pipe.connect_with_retry(&policy).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENXIO));
+        // the backoff is the same whatever the policy, only how it's waited out differs
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        thread_cpu_time() - before
+    };
+    let busy = cpu_while_connecting(SpinPolicy::Busy);
+    let sleep = cpu_while_connecting(SpinPolicy::default());
+    assert!(sleep * 5 < busy, "sleep {:?} busy {:?}", sleep, busy);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
@@ -0,0 +1,106 @@
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+
+use libc;
+
+use core::reader::PipeReader;
+use core::writer::PipeWriter;
+
+/**
+move up to `max` bytes from `src` into `dst` inside the kernel with splice(2), no copy through userspace
+
+returns the bytes moved, `Ok(0)` means the writer of `src` hung up. Blocks like a plain read/write unless the fds are non-blocking
+*/
+pub fn splice_between(src: &PipeReader, dst: &PipeWriter, max: usize) -> io::Result<usize> {
+    loop {
+        let ret = unsafe {
+            libc::splice(
+                src.as_raw_fd(),
+                ptr::null_mut(),
+                dst.as_raw_fd(),
+                ptr::null_mut(),
+                max,
+                libc::SPLICE_F_MOVE,
+            )
+        };
+        if ret == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        return Ok(ret as usize);
+    }
+}
+
+/**
+duplicate up to `max` bytes queued in `src` into `dst` with tee(2), without consuming them from `src`
+
+a following read (or splice) on `src` still sees the same bytes
+*/
+pub fn tee_between(src: &PipeReader, dst: &PipeWriter, max: usize) -> io::Result<usize> {
+    loop {
+        let ret = unsafe { libc::tee(src.as_raw_fd(), dst.as_raw_fd(), max, 0) };
+        if ret == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        return Ok(ret as usize);
+    }
+}
+
+#[test]
+fn test_splice_between_fifos() {
+    use std::io::{Read, Write};
+
+    const LEN: usize = 64 * 1024;
+    let (src_pipe, src_reader, mut src_writer) = ::test_pair("splice_src");
+    let (dst_pipe, mut dst_reader, dst_writer) = ::test_pair("splice_dst");
+    let payload: Vec<u8> = (0..LEN).map(|i| (i % 251) as u8).collect();
+
+    let producer = {
+        let payload = payload.clone();
+        std::thread::spawn(move || src_writer.write_all(&payload).unwrap())
+    };
+    let consumer = std::thread::spawn(move || {
+        let mut received = Vec::new();
+        dst_reader.read_to_end(&mut received).unwrap();
+        received
+    });
+    let mut moved = 0;
+    while moved < LEN {
+        let n = splice_between(&src_reader, &dst_writer, LEN - moved).unwrap();
+        assert_ne!(n, 0, "unexpected EOF after {} bytes", moved);
+        moved += n;
+    }
+    drop(dst_writer);
+    producer.join().unwrap();
+    assert_eq!(consumer.join().unwrap(), payload);
+    std::fs::remove_file(src_pipe.path()).unwrap();
+    std::fs::remove_file(dst_pipe.path()).unwrap();
+}
+
+#[test]
+fn test_tee_between_keeps_source_data() {
+    use std::io::{Read, Write};
+
+    let (src_pipe, mut src_reader, mut src_writer) = ::test_pair("tee_src");
+    let (dst_pipe, mut dst_reader, dst_writer) = ::test_pair("tee_dst");
+    src_writer.write_all(b"hello").unwrap();
+    assert_eq!(tee_between(&src_reader, &dst_writer, 1024).unwrap(), 5);
+    drop((src_writer, dst_writer));
+
+    let mut from_src = Vec::new();
+    src_reader.read_to_end(&mut from_src).unwrap();
+    let mut from_dst = Vec::new();
+    dst_reader.read_to_end(&mut from_dst).unwrap();
+    assert_eq!(from_src, b"hello");
+    assert_eq!(from_dst, b"hello");
+    std::fs::remove_file(src_pipe.path()).unwrap();
+    std::fs::remove_file(dst_pipe.path()).unwrap();
+}
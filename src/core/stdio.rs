@@ -0,0 +1,60 @@
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::process::Stdio;
+
+use libc;
+
+use core::pipe::NamedPipe;
+use core::sys::set_nonblocking;
+
+impl NamedPipe {
+    /**
+    the read end as a child's stdin, like `cat < my_pipe`: `Command::new("cat").stdin(pipe.as_stdio_read()?)`
+
+    opened with `O_NONBLOCK` so this doesn't wait for a writer (which would deadlock a parent that only opens the
+    write end after spawning), then switched back to blocking for the child. Until some writer has the FIFO open a read
+    returns EOF right away, so attach the writer (`try_open_writer` succeeds now) before spawning
+    */
+    pub fn as_stdio_read(&self) -> io::Result<Stdio> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(self.path())?;
+        set_nonblocking(file.as_raw_fd(), false)?;
+        Ok(Stdio::from(file))
+    }
+
+    /**
+    the write end as a child's stdout or stderr, like `echo hello > my_pipe`
+
+    a reader has to be attached already, otherwise this fails with `ENXIO` right away instead of blocking
+    the parent before the child even exists, see `try_open_writer`
+    */
+    pub fn as_stdio_write(&self) -> io::Result<Stdio> {
+        let writer = self.try_open_writer()?;
+        Ok(Stdio::from(writer.into_file()))
+    }
+}
+
+#[test]
+fn test_cat_reads_from_fifo() {
+    use std::io::{Read, Write};
+    use std::process::Command;
+
+    let pipe = ::test_pipe("stdio_cat");
+    let stdin = pipe.as_stdio_read().unwrap();
+    // before the spawn: cat reading a FIFO which no writer opened yet would see EOF and exit
+    let mut writer = pipe.try_open_writer().unwrap();
+    let mut child = Command::new("cat").stdin(stdin).stdout(Stdio::piped()).spawn().unwrap();
+    writer.write_all(b"hello\n").unwrap();
+    drop(writer);
+    let mut out = String::new();
+    child.stdout.take().unwrap().read_to_string(&mut out).unwrap();
+    assert!(child.wait().unwrap().success());
+    assert_eq!(out, "hello\n");
+
+    assert_eq!(pipe.as_stdio_write().unwrap_err().raw_os_error(), Some(libc::ENXIO));
+    std::fs::remove_file(pipe.path()).unwrap();
+}
@@ -0,0 +1,218 @@
+//! thin helpers shared by the syscall wrappers, same idea as `cvt` in std::sys
+use std::ffi::CString;
+use std::io::{self, IoSlice, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::ptr;
+use std::time::Duration;
+
+use libc;
+
+use core::errno::{errno_to_error, last_errno};
+use core::spin::SpinPolicy;
+use core::error::PipeError;
+
+/// consecutive `Ok(0)` writes before giving up on a writer which makes no progress
+pub(crate) const MAX_STALLED_WRITES: usize = 8;
+
+/// return types of syscalls which report failure as -1, `int` and `ssize_t`
+pub(crate) trait IsMinusOne: Copy {
+    fn is_minus_one(self) -> bool;
+}
+
+impl IsMinusOne for libc::c_int {
+    fn is_minus_one(self) -> bool {
+        self == -1
+    }
+}
+
+impl IsMinusOne for libc::ssize_t {
+    fn is_minus_one(self) -> bool {
+        self == -1
+    }
+}
+
+/// turn the C convention "-1 and errno" into `io::Result`
+pub(crate) fn cvt<T: IsMinusOne>(ret: T) -> io::Result<T> {
+    if ret.is_minus_one() {
+        Err(errno_to_error(last_errno()))
+    } else {
+        Ok(ret)
+    }
+}
+
+/// one `read(2)` on a raw fd through `cvt`, retried on `EINTR`
+#[cfg(feature = "select")]
+pub(crate) fn read_fd(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match cvt(unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) }) {
+            Ok(n) => return Ok(n as usize),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/**
+Path -> CString, the conversion which is the source of "data provided contains a nul byte"
+
+every path goes through here before a syscall, an interior NUL is `PipeError::NulInPath` with its offset
+instead of being cut off or doubled up with the terminator
+*/
+pub(crate) fn path_to_cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|e| PipeError::NulInPath { offset: e.nul_position() }.into())
+}
+
+/// poll(2) timeout argument, `None` blocks forever
+pub(crate) fn poll_timeout_ms(timeout: Option<Duration>) -> libc::c_int {
+    match timeout {
+        // round up so a sub-millisecond timeout doesn't turn into a busy non-blocking poll
+        Some(timeout) => {
+            let ms = timeout.as_millis() + u128::from(timeout.subsec_nanos() % 1_000_000 != 0);
+            ms.min(libc::c_int::MAX as u128) as libc::c_int
+        }
+        None => -1,
+    }
+}
+
+/// poll a single fd, return the revents or 0 on timeout, `None` timeout blocks forever
+pub(crate) fn poll_fd(fd: RawFd, events: libc::c_short, timeout: Option<Duration>) -> io::Result<libc::c_short> {
+    let timeout_ms = poll_timeout_ms(timeout);
+    let mut pollfd = libc::pollfd { fd, events, revents: 0 };
+    loop {
+        match cvt(unsafe { libc::poll(&mut pollfd, 1, timeout_ms) }) {
+            Ok(0) => return Ok(0),
+            Ok(_) => return Ok(pollfd.revents),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// toggle O_NONBLOCK on the open file description, returns whether it was set before
+pub(crate) fn set_nonblocking(fd: RawFd, nonblocking: bool) -> io::Result<bool> {
+    let flags = cvt(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+    let new_flags = if nonblocking {
+        flags | libc::O_NONBLOCK
+    } else {
+        flags & !libc::O_NONBLOCK
+    };
+    if new_flags != flags {
+        cvt(unsafe { libc::fcntl(fd, libc::F_SETFL, new_flags) })?;
+    }
+    Ok(flags & libc::O_NONBLOCK != 0)
+}
+
+/**
+run `f` (a single write) with SIGPIPE blocked on this thread
+
+if `f` fails with EPIPE the SIGPIPE it raised is left pending by the mask, it's taken with sigwait before the old mask comes back,
+unless a SIGPIPE was already pending before, which then belongs to the caller
+*/
+pub(crate) fn with_sigpipe_blocked<T, F: FnOnce() -> io::Result<T>>(f: F) -> io::Result<T> {
+    unsafe {
+        let mut sigpipe = std::mem::zeroed::<libc::sigset_t>();
+        libc::sigemptyset(&mut sigpipe);
+        libc::sigaddset(&mut sigpipe, libc::SIGPIPE);
+        let was_pending = sigpipe_pending();
+        let mut old_mask = std::mem::zeroed::<libc::sigset_t>();
+        // pthread functions return the errno instead of setting it
+        let ret = libc::pthread_sigmask(libc::SIG_BLOCK, &sigpipe, &mut old_mask);
+        if ret != 0 {
+            return Err(io::Error::from_raw_os_error(ret));
+        }
+        let res = f();
+        let broken_pipe = match res {
+            Err(ref e) => e.raw_os_error() == Some(libc::EPIPE),
+            Ok(_) => false,
+        };
+        if broken_pipe && !was_pending && sigpipe_pending() {
+            let mut sig = 0;
+            libc::sigwait(&sigpipe, &mut sig);
+        }
+        libc::pthread_sigmask(libc::SIG_SETMASK, &old_mask, ptr::null_mut());
+        res
+    }
+}
+
+unsafe fn sigpipe_pending() -> bool {
+    let mut pending = std::mem::zeroed::<libc::sigset_t>();
+    libc::sigpending(&mut pending);
+    libc::sigismember(&pending, libc::SIGPIPE) == 1
+}
+
+/// bytes the kernel holds for the pipe behind `fd`, `ioctl(FIONREAD)`
+pub(crate) fn fionread(fd: RawFd) -> io::Result<usize> {
+    let mut n: libc::c_int = 0;
+    cvt(unsafe { libc::ioctl(fd, libc::FIONREAD, &mut n) })?;
+    Ok(n as usize)
+}
+
+/// O_RDONLY / O_WRONLY / O_RDWR of the open file description
+pub(crate) fn access_mode(fd: RawFd) -> io::Result<libc::c_int> {
+    Ok(cvt(unsafe { libc::fcntl(fd, libc::F_GETFL) })? & libc::O_ACCMODE)
+}
+
+/**
+`write_all` over several slices, which doesn't trust a `write` returning 0 to ever make progress
+
+a read of 0 means EOF, a write of 0 only means nothing was written: retried `MAX_STALLED_WRITES` times in a row with
+a `spin` pause in between, then `WriteZero`
+*/
+pub(crate) fn write_all_vectored_checked<W: Write>(
+    writer: &mut W,
+    mut bufs: &mut [IoSlice],
+    spin: SpinPolicy,
+) -> io::Result<()> {
+    IoSlice::advance_slices(&mut bufs, 0);
+    let mut stalls = 0;
+    while !bufs.is_empty() {
+        match writer.write_vectored(bufs) {
+            Ok(0) => {
+                stalls += 1;
+                if stalls >= MAX_STALLED_WRITES {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        format!("writer made no progress in {} writes", stalls),
+                    ));
+                }
+                spin.pause();
+            }
+            Ok(n) => {
+                stalls = 0;
+                IoSlice::advance_slices(&mut bufs, n);
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_write_gives_up_on_stuck_writer() {
+    struct Stuck(usize);
+    impl Write for Stuck {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            self.0 += 1;
+            Ok(0)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut stuck = Stuck(0);
+    let err = write_all_vectored_checked(&mut stuck, &mut [IoSlice::new(b"hello")], SpinPolicy::Busy).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    assert_eq!(stuck.0, MAX_STALLED_WRITES);
+}
+
+#[test]
+fn test_nul_in_path_offset() {
+    let pipe = ::NamedPipe::new("/tmp/foo\0bar");
+    let err = pipe.create(0o600).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(matches!(PipeError::downcast(&err), Some(&PipeError::NulInPath { offset: 8 })), "{:?}", err);
+    assert!(err.to_string().contains("offset 8"));
+}
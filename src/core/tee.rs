@@ -0,0 +1,51 @@
+use std::io::{self, Read, Write};
+
+use core::reader::PipeReader;
+
+/// every byte read from the pipe is also written to `sink`, like `tee(1)` for a reader
+#[derive(Debug)]
+pub struct TeeReader<W: Write> {
+    reader: PipeReader,
+    sink: W,
+}
+
+impl<W: Write> TeeReader<W> {
+    pub fn new(reader: PipeReader, sink: W) -> Self {
+        TeeReader { reader, sink }
+    }
+
+    pub fn sink(&self) -> &W {
+        &self.sink
+    }
+
+    pub fn into_parts(self) -> (PipeReader, W) {
+        (self.reader, self.sink)
+    }
+}
+
+impl<W: Write> Read for TeeReader<W> {
+    /// a sink error fails the read, the bytes are still in `buf` but the sink copy is incomplete
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.sink.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_tee_reader_copies_to_sink() {
+    let (pipe, reader, mut writer) = ::test_pair("tee_reader");
+    let payload: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+    let producer = {
+        let payload = payload.clone();
+        std::thread::spawn(move || writer.write_all(&payload).unwrap())
+    };
+    let mut tee = TeeReader::new(reader, Vec::new());
+    let mut received = Vec::new();
+    tee.read_to_end(&mut received).unwrap();
+    producer.join().unwrap();
+    let (_reader, sink) = tee.into_parts();
+    assert_eq!(received, payload);
+    assert_eq!(sink, payload);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
@@ -0,0 +1,103 @@
+/*!
+diagnostics for the classic FIFO hang: a blocking open waits for its peer forever and says nothing
+
+an `OpenWatchdog` only reports, after the grace period it fires its hook once while the open keeps waiting
+*/
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use core::pipe::NamedPipe;
+use core::reader::PipeReader;
+use core::writer::PipeWriter;
+
+type StallHook = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// fires a warning if a blocking open is still waiting for the peer after `grace`
+#[derive(Clone)]
+pub struct OpenWatchdog {
+    grace: Duration,
+    hook: StallHook,
+}
+
+impl fmt::Debug for OpenWatchdog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OpenWatchdog").field("grace", &self.grace).finish()
+    }
+}
+
+impl OpenWatchdog {
+    /// the warning goes to stderr
+    pub fn new(grace: Duration) -> Self {
+        OpenWatchdog {
+            grace,
+            hook: Arc::new(|warning| eprintln!("warning: {}", warning)),
+        }
+    }
+
+    /// send the warning somewhere else than stderr, e.g. into the application's logger
+    pub fn on_stall<F: Fn(&str) + Send + Sync + 'static>(mut self, hook: F) -> Self {
+        self.hook = Arc::new(hook);
+        self
+    }
+
+    fn watch<T, F: FnOnce() -> io::Result<T>>(&self, path: &Path, open: F) -> io::Result<T> {
+        let (done, wait) = mpsc::channel::<()>();
+        let grace = self.grace;
+        let hook = self.hook.clone();
+        let path = path.to_path_buf();
+        let checker = thread::spawn(move || {
+            // the sender is dropped once the open returns, however it went
+            if let Err(mpsc::RecvTimeoutError::Timeout) = wait.recv_timeout(grace) {
+                hook(&format!(
+                    "open() on FIFO {} has been blocked for {:?}, is the peer attached?",
+                    path.display(),
+                    grace
+                ));
+            }
+        });
+        let res = open();
+        drop(done);
+        let _ = checker.join();
+        res
+    }
+}
+
+impl NamedPipe {
+    /// `open_reader` watched by `watchdog`
+    pub fn open_reader_watched(&self, watchdog: &OpenWatchdog) -> io::Result<PipeReader> {
+        watchdog.watch(self.path(), || self.open_reader())
+    }
+
+    /// `open_writer` watched by `watchdog`
+    pub fn open_writer_watched(&self, watchdog: &OpenWatchdog) -> io::Result<PipeWriter> {
+        watchdog.watch(self.path(), || self.open_writer())
+    }
+}
+
+#[test]
+fn test_watchdog_warns_about_pending_open() {
+    let pipe = ::test_pipe("open_watchdog");
+    let (warnings, warned) = mpsc::channel();
+    let warnings = std::sync::Mutex::new(warnings);
+    let watchdog = OpenWatchdog::new(Duration::from_millis(20)).on_stall(move |warning| {
+        warnings.lock().unwrap().send(warning.to_string()).unwrap();
+    });
+    let writer = {
+        let pipe = pipe.clone();
+        thread::spawn(move || pipe.open_writer_watched(&watchdog).unwrap())
+    };
+    let warning = warned.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(warning.contains(&pipe.path().display().to_string()), "{}", warning);
+    assert!(warning.contains("is the peer attached?"));
+
+    // attaching the peer lets the open finish, the watchdog only fires once
+    let _reader = pipe.open_reader().unwrap();
+    drop(writer.join().unwrap());
+    assert!(warned.try_recv().is_err());
+    std::fs::remove_file(pipe.path()).unwrap();
+}
@@ -0,0 +1,499 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, IoSlice, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use libc;
+
+use core::clock::{Clock, SystemClock};
+use core::pipe::NamedPipe;
+use core::spin::SpinPolicy;
+use core::sys::{
+    access_mode, cvt, poll_fd, set_nonblocking, with_sigpipe_blocked, write_all_vectored_checked, MAX_STALLED_WRITES,
+};
+
+/// the formatting scratch buffer is given back to the allocator when a huge message made it bigger than this
+const SCRATCH_KEEP: usize = 64 * 1024;
+
+thread_local! {
+    static FMT_SCRATCH: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// what went through a `PipeWriter` so far, see `PipeWriter::stats`
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteStats {
+    /// successful write/writev calls
+    pub writes: u64,
+    pub bytes: u64,
+    /// failed calls, not counting `WouldBlock` and `Interrupted` which are retried
+    pub errors: u64,
+}
+
+/// write end of a FIFO
+#[derive(Debug)]
+pub struct PipeWriter {
+    file: File,
+    #[cfg(feature = "stats")]
+    stats: WriteStats,
+    block_sigpipe: bool,
+    /// pause between writes which wrote 0 bytes
+    spin: SpinPolicy,
+    #[cfg(all(feature = "capacity", target_os = "linux"))]
+    pub(crate) strict_capacity: bool,
+    /// the FIFO this was opened from, `None` for an anonymous pipe or a raw fd
+    origin: Option<NamedPipe>,
+}
+
+impl PipeWriter {
+    pub(crate) fn from_file(file: File) -> Self {
+        PipeWriter {
+            file,
+            #[cfg(feature = "stats")]
+            stats: WriteStats::default(),
+            block_sigpipe: false,
+            spin: SpinPolicy::default(),
+            #[cfg(all(feature = "capacity", target_os = "linux"))]
+            strict_capacity: false,
+            origin: None,
+        }
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> WriteStats {
+        self.stats
+    }
+
+    #[cfg(feature = "stats")]
+    fn record(&mut self, res: io::Result<usize>) -> io::Result<usize> {
+        match res {
+            Ok(n) => {
+                self.stats.writes += 1;
+                self.stats.bytes += n as u64;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => self.stats.errors += 1,
+        }
+        res
+    }
+
+    #[cfg(not(feature = "stats"))]
+    fn record(&mut self, res: io::Result<usize>) -> io::Result<usize> {
+        res
+    }
+
+    #[cfg(feature = "stdio")]
+    pub(crate) fn into_file(self) -> File {
+        self.file
+    }
+
+    pub(crate) fn with_origin(mut self, pipe: &NamedPipe) -> Self {
+        self.origin = Some(pipe.clone());
+        self
+    }
+
+    /**
+    replace the fd in place by a fresh open of the same FIFO, e.g. after `BrokenPipe` because the reader restarted
+
+    blocks like `open_writer` until a reader is there. The file status flags (`O_APPEND`, `O_DIRECT`, ...), close-on-exec
+    and (capacity feature, Linux) the pipe buffer size are carried over to the new fd, the old one is closed once the open
+    succeeded. The manual counterpart to `ReconnectingWriter`, fails with `InvalidInput` for a writer which wasn't opened from a path
+    */
+    pub fn reopen(&mut self) -> io::Result<()> {
+        let pipe = match self.origin {
+            Some(ref pipe) => pipe.clone(),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "writer wasn't opened from a FIFO path, nothing to reopen",
+                ))
+            }
+        };
+        let fd = self.as_raw_fd();
+        let status_flags = cvt(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+        let fd_flags = cvt(unsafe { libc::fcntl(fd, libc::F_GETFD) })?;
+        #[cfg(all(feature = "capacity", target_os = "linux"))]
+        let capacity = self.capacity()?;
+
+        let file = OpenOptions::new().write(true).open(pipe.path())?;
+        // F_SETFL ignores the access mode bits in status_flags
+        cvt(unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFL, status_flags) })?;
+        cvt(unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFD, fd_flags) })?;
+        self.file = file;
+        #[cfg(all(feature = "capacity", target_os = "linux"))]
+        self.ensure_capacity(capacity)?;
+        Ok(())
+    }
+
+    /**
+    block SIGPIPE on the calling thread around each write of this handle
+
+    a write to a FIFO whose reader is gone then fails with a plain `BrokenPipe` even if the process keeps the
+    default SIGPIPE disposition (which kills it), and the signal raised by that write is consumed before the mask is restored.
+    Neither the process wide disposition nor other threads are touched, unlike `signal(SIGPIPE, SIG_IGN)`
+    */
+    pub fn set_block_sigpipe(&mut self, block: bool) {
+        self.block_sigpipe = block;
+    }
+
+    /// how a write loop waits before retrying a write which wrote 0 bytes, a short sleep by default
+    #[cfg(feature = "retry")]
+    pub fn set_spin_policy(&mut self, spin: SpinPolicy) {
+        self.spin = spin;
+    }
+
+    /// poll until the pipe buffer has room, `false` if `timeout` passed first and `None` waits forever
+    pub fn wait_writable(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        Ok(poll_fd(self.as_raw_fd(), libc::POLLOUT, timeout)? != 0)
+    }
+
+    /**
+    keep calling writev(2) until every slice is fully written
+
+    a writev stopping in the middle of a slice resumes from there, so `bufs` is left modified afterwards (as with std's unstable `write_all_vectored`).
+    Same no-progress rule as `write_message`: `WriteZero` after `MAX_STALLED_WRITES` writes of 0 bytes in a row
+    */
+    pub fn write_all_vectored(&mut self, bufs: &mut [IoSlice]) -> io::Result<()> {
+        let spin = self.spin;
+        write_all_vectored_checked(self, bufs, spin)
+    }
+
+    /**
+    write the UTF-8 bytes of `s` and nothing else
+
+    unlike a C string a Rust `str` carries its length and needs no `\0` terminator on a byte stream,
+    a `\0` like in the `b"hello\n\0"` demo would just show up as part of the text on the reader side
+    */
+    pub fn write_str(&mut self, s: &str) -> io::Result<()> {
+        self.write_all(s.as_bytes())
+    }
+
+    /// `write_str` plus one `\n`, in a single writev so a short line stays one atomic write
+    pub fn writeln_str(&mut self, s: &str) -> io::Result<()> {
+        self.write_all_vectored(&mut [IoSlice::new(s.as_bytes()), IoSlice::new(b"\n")])
+    }
+
+    /**
+    write all of `buf` within `timeout`, else `TimedOut`
+
+    the fd is non-blocking while this runs, so a `write` can never hang past the deadline.
+    A reader that stopped reading ends in `TimedOut`, `write` returning 0 in `WriteZero`, neither spins forever
+    */
+    pub fn write_timeout(&mut self, buf: &[u8], timeout: Duration) -> io::Result<()> {
+        self.write_timeout_on(buf, timeout, &SystemClock)
+    }
+
+    /// `write_timeout` measuring the deadline on `clock`
+    #[cfg(feature = "clock")]
+    pub fn write_timeout_with_clock(&mut self, buf: &[u8], timeout: Duration, clock: &dyn Clock) -> io::Result<()> {
+        self.write_timeout_on(buf, timeout, clock)
+    }
+
+    fn write_timeout_on(&mut self, buf: &[u8], timeout: Duration, clock: &dyn Clock) -> io::Result<()> {
+        let was_nonblocking = set_nonblocking(self.as_raw_fd(), true)?;
+        let res = self.write_until(buf, clock.now() + timeout, timeout, clock);
+        set_nonblocking(self.as_raw_fd(), was_nonblocking)?;
+        res
+    }
+
+    fn write_until(&mut self, buf: &[u8], deadline: Instant, timeout: Duration, clock: &dyn Clock) -> io::Result<()> {
+        let mut written = 0;
+        let mut stalls = 0;
+        while written < buf.len() {
+            let remaining = deadline.saturating_duration_since(clock.now());
+            if remaining == Duration::from_secs(0)
+                || clock.poll_fd(self.as_raw_fd(), libc::POLLOUT, Some(remaining))? == 0
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("wrote {} of {} bytes within {:?}", written, buf.len(), timeout),
+                ));
+            }
+            match self.write(&buf[written..]) {
+                Ok(0) => {
+                    stalls += 1;
+                    if stalls >= MAX_STALLED_WRITES {
+                        return Err(io::Error::new(io::ErrorKind::WriteZero, "pipe writer made no progress"));
+                    }
+                    // poll says writable right away again, don't spin on it
+                    self.spin.pause();
+                }
+                Ok(n) => {
+                    stalls = 0;
+                    written += n;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    `from_raw_fd` which first checks the access mode with `fcntl(F_GETFL)`
+
+    a read-only fd is rejected with `InvalidInput` instead of failing later with a confusing `EBADF`,
+    on error the fd is not taken over and the caller still has to close it
+
+    # Safety
+    same as `FromRawFd::from_raw_fd`: on success `fd` must be an open fd owned by nobody else
+    */
+    pub unsafe fn from_raw_fd_checked(fd: RawFd) -> io::Result<Self> {
+        if access_mode(fd)? == libc::O_RDONLY {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("fd {} is opened read-only, can't be used as a pipe writer", fd),
+            ));
+        }
+        Ok(Self::from_raw_fd(fd))
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let res = if self.block_sigpipe {
+            let file = &mut self.file;
+            with_sigpipe_blocked(|| file.write(buf))
+        } else {
+            self.file.write(buf)
+        };
+        self.record(res)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        let res = if self.block_sigpipe {
+            let file = &mut self.file;
+            with_sigpipe_blocked(|| file.write_vectored(bufs))
+        } else {
+            self.file.write_vectored(bufs)
+        };
+        self.record(res)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    /**
+    render the whole `write!` into a thread local buffer first, then hand it to the pipe at once
+
+    the default `write_fmt` issues a write per formatting piece, which other producers on the same FIFO can get between.
+    A rendered message of at most PIPE_BUF bytes is then one atomic write, a line can't come out interleaved
+    */
+    fn write_fmt(&mut self, args: fmt::Arguments) -> io::Result<()> {
+        FMT_SCRATCH.with(|scratch| match scratch.try_borrow_mut() {
+            Ok(mut scratch) => {
+                scratch.clear();
+                scratch.write_fmt(args)?;
+                let res = self.write_all(&scratch);
+                if scratch.capacity() > SCRATCH_KEEP {
+                    *scratch = Vec::new();
+                }
+                res
+            }
+            // a Display impl writing to a pipe itself, don't fight over the buffer
+            Err(_) => {
+                let mut rendered = Vec::new();
+                rendered.write_fmt(args)?;
+                self.write_all(&rendered)
+            }
+        })
+    }
+}
+
+impl AsRawFd for PipeWriter {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for PipeWriter {
+    fn into_raw_fd(self) -> RawFd {
+        self.file.into_raw_fd()
+    }
+}
+
+impl FromRawFd for PipeWriter {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        PipeWriter::from_file(File::from_raw_fd(fd))
+    }
+}
+
+#[test]
+fn test_from_raw_fd_checked_rejects_read_only() {
+    let (pipe, reader, writer) = ::test_pair("from_raw_fd_checked");
+    let read_fd = reader.into_raw_fd();
+    let err = unsafe { PipeWriter::from_raw_fd_checked(read_fd) }.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let reader = unsafe { ::PipeReader::from_raw_fd_checked(read_fd) }.unwrap();
+
+    let write_fd = writer.into_raw_fd();
+    assert!(unsafe { ::PipeReader::from_raw_fd_checked(write_fd) }.is_err());
+    let writer = unsafe { PipeWriter::from_raw_fd_checked(write_fd) }.unwrap();
+    drop((reader, writer));
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_wait_writable() {
+    let (pipe, reader, mut writer) = ::test_pair("wait_writable");
+    assert!(writer.wait_writable(Some(Duration::from_millis(20))).unwrap());
+    // fill the pipe buffer up, then nothing more fits
+    ::core::sys::set_nonblocking(writer.as_raw_fd(), true).unwrap();
+    let chunk = [0u8; 4096];
+    for &size in &[4096, 1] {
+        while writer.write(&chunk[..size]).is_ok() {}
+    }
+    assert!(!writer.wait_writable(Some(Duration::from_millis(20))).unwrap());
+    drop(reader);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_write_timeout_stuck_reader() {
+    let (pipe, reader, mut writer) = ::test_pair("write_timeout_stuck");
+    let start = Instant::now();
+    // far more than the pipe buffer and nobody reads
+    let err = writer.write_timeout(&vec![0u8; 1 << 20], Duration::from_millis(50)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    // blocking mode is restored
+    assert!(!::core::sys::set_nonblocking(writer.as_raw_fd(), false).unwrap());
+    drop(reader);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_write_all_vectored_small_pipe() {
+    use std::io::Read;
+
+    let (pipe, mut reader, mut writer) = ::test_pair("write_all_vectored");
+    #[cfg(target_os = "linux")]
+    ::core::sys::cvt(unsafe { libc::fcntl(writer.as_raw_fd(), libc::F_SETPIPE_SZ, 4096) }).unwrap();
+    let parts: Vec<Vec<u8>> = [100usize, 10_000, 50_000]
+        .iter()
+        .enumerate()
+        .map(|(i, &len)| vec![i as u8 + 1; len])
+        .collect();
+    let consumer = std::thread::spawn(move || {
+        let mut received = Vec::new();
+        reader.read_to_end(&mut received).unwrap();
+        received
+    });
+    let mut slices: Vec<IoSlice> = parts.iter().map(|part| IoSlice::new(part)).collect();
+    writer.write_all_vectored(&mut slices).unwrap();
+    drop(writer);
+    assert_eq!(consumer.join().unwrap(), parts.concat());
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_block_sigpipe_gives_broken_pipe() {
+    let (reader, mut writer) = ::Pipe::anonymous().unwrap();
+    drop(reader);
+    writer.set_block_sigpipe(true);
+    let disposition_before = unsafe { libc::signal(libc::SIGPIPE, libc::SIG_IGN) };
+    unsafe { libc::signal(libc::SIGPIPE, disposition_before) };
+
+    // in a child with the default disposition a stray SIGPIPE would kill it
+    let pid = unsafe { libc::fork() };
+    assert_ne!(pid, -1);
+    if pid == 0 {
+        unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) };
+        let code = match writer.write(b"x") {
+            Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => 0,
+            _ => 1,
+        };
+        unsafe { libc::_exit(code) };
+    }
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status), "child killed by signal {}", libc::WTERMSIG(status));
+    assert_eq!(libc::WEXITSTATUS(status), 0);
+
+    assert_eq!(writer.write(b"x").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    let disposition_after = unsafe { libc::signal(libc::SIGPIPE, libc::SIG_IGN) };
+    unsafe { libc::signal(libc::SIGPIPE, disposition_after) };
+    assert_eq!(disposition_before, disposition_after);
+}
+
+#[test]
+fn test_write_fmt_lines_not_interleaved() {
+    use std::io::BufRead;
+
+    const LINES: usize = 2000;
+    let (pipe, reader, writer) = ::test_pair("write_fmt");
+    let writers = vec![writer, pipe.open_writer().unwrap()];
+    let producers: Vec<_> = writers
+        .into_iter()
+        .enumerate()
+        .map(|(id, mut writer)| {
+            std::thread::spawn(move || {
+                for i in 0..LINES {
+                    writeln!(writer, "thread {} line {}", id, i).unwrap();
+                }
+            })
+        })
+        .collect();
+    let consumer = std::thread::spawn(move || {
+        let mut counts = [0usize; 2];
+        for line in io::BufReader::new(reader).lines() {
+            let line = line.unwrap();
+            let mut words = line.split(' ');
+            assert_eq!(words.next(), Some("thread"), "garbled line {:?}", line);
+            let id: usize = words.next().unwrap().parse().unwrap();
+            assert_eq!(words.next(), Some("line"), "garbled line {:?}", line);
+            let i: usize = words.next().unwrap().parse().unwrap();
+            assert_eq!(i, counts[id], "garbled line {:?}", line);
+            assert_eq!(words.next(), None, "garbled line {:?}", line);
+            counts[id] += 1;
+        }
+        counts
+    });
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    assert_eq!(consumer.join().unwrap(), [LINES, LINES]);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_reopen_after_reader_restart() {
+    use std::io::Read;
+
+    let (pipe, reader, mut writer) = ::test_pair("reopen");
+    #[cfg(all(feature = "capacity", target_os = "linux"))]
+    let capacity = writer.set_capacity(256 * 1024).unwrap();
+    drop(reader);
+    assert_eq!(writer.write(b"lost").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+
+    // the old writer fd is still open, so this open doesn't wait
+    let mut reader = pipe.open_reader().unwrap();
+    writer.reopen().unwrap();
+    #[cfg(all(feature = "capacity", target_os = "linux"))]
+    assert_eq!(writer.capacity().unwrap(), capacity);
+    assert_ne!(unsafe { libc::fcntl(writer.as_raw_fd(), libc::F_GETFD) } & libc::FD_CLOEXEC, 0);
+    writer.write_all(b"delivered").unwrap();
+    drop(writer);
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, b"delivered");
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_write_str_has_no_nul() {
+    use std::io::Read;
+
+    let (pipe, mut reader, mut writer) = ::test_pair("write_str");
+    writer.write_str("hello").unwrap();
+    writer.writeln_str("world").unwrap();
+    drop(writer);
+    let mut text = String::new();
+    reader.read_to_string(&mut text).unwrap();
+    assert_eq!(text, "helloworld\n");
+    assert!(!text.contains('\0'));
+    std::fs::remove_file(pipe.path()).unwrap();
+}
@@ -0,0 +1,133 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use core::reader::PipeReader;
+use core::PipeSelector;
+
+/**
+the dual of `Broadcaster`: framed messages of several FIFOs merged into one stream in the order they arrive
+
+each message comes tagged with the index of its source in the `Vec` given to `new`. A source whose writers all
+hung up is dropped, `recv` returns `Ok(None)` once every one of them is gone. Reading a message blocks until the
+frame is complete, so a writer which sends half a frame and stalls stalls the fan-in as well
+*/
+#[derive(Debug)]
+pub struct FanIn {
+    /// `None` once that source hit EOF, indices stay stable
+    sources: Vec<Option<PipeReader>>,
+    selector: PipeSelector,
+    active: usize,
+    /// first index looked at next time, so one busy source can't starve the others
+    next: usize,
+}
+
+impl FanIn {
+    pub fn new(readers: Vec<PipeReader>) -> io::Result<Self> {
+        let mut selector = PipeSelector::new();
+        for reader in &readers {
+            selector.register(reader)?;
+        }
+        Ok(FanIn {
+            active: readers.len(),
+            sources: readers.into_iter().map(Some).collect(),
+            selector,
+            next: 0,
+        })
+    }
+
+    /// sources which haven't hit EOF yet
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// next `(source index, payload)`, `TimedOut` if none arrives in time (`None` waits forever)
+    pub fn recv(&mut self, timeout: Option<Duration>) -> io::Result<Option<(usize, Vec<u8>)>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        while self.active > 0 {
+            let index = match self.next_ready(deadline)? {
+                Some(index) => index,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("no message from {} sources within {:?}", self.active, timeout),
+                    ))
+                }
+            };
+            self.next = (index + 1) % self.sources.len();
+            let message = match self.sources[index] {
+                Some(ref mut reader) => reader.read_message()?,
+                None => continue,
+            };
+            match message {
+                Some(payload) => return Ok(Some((index, payload))),
+                None => {
+                    self.selector.deregister(index)?;
+                    self.sources[index] = None;
+                    self.active -= 1;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// the first ready source at or after `next`, wrapping around
+    fn next_ready(&mut self, deadline: Option<Instant>) -> io::Result<Option<usize>> {
+        let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let ready = self.selector.select(timeout)?;
+        Ok(ready
+            .iter()
+            .find(|&&index| index >= self.next)
+            .or_else(|| ready.first())
+            .cloned())
+    }
+}
+
+#[test]
+fn test_fan_in_tags_sources() {
+    const MESSAGES: u32 = 50;
+
+    let pairs: Vec<_> = (0..3).map(|i| ::test_pair(&format!("fan_in_{}", i))).collect();
+    let mut readers = Vec::new();
+    let mut producers = Vec::new();
+    let mut pipes = Vec::new();
+    for (source, (pipe, reader, mut writer)) in pairs.into_iter().enumerate() {
+        pipes.push(pipe);
+        readers.push(reader);
+        producers.push(std::thread::spawn(move || {
+            for i in 0..MESSAGES {
+                let mut message = vec![source as u8];
+                message.extend_from_slice(&i.to_be_bytes());
+                writer.write_message(&message).unwrap();
+                if i % 10 == source as u32 {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+        }));
+    }
+    let mut fan_in = FanIn::new(readers).unwrap();
+    let mut received = vec![Vec::new(); 3];
+    while let Some((source, message)) = fan_in.recv(Some(Duration::from_secs(5))).unwrap() {
+        assert_eq!(message[0] as usize, source);
+        received[source].push(u32::from_be_bytes([message[1], message[2], message[3], message[4]]));
+    }
+    assert_eq!(fan_in.active(), 0);
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    for messages in &received {
+        assert_eq!(*messages, (0..MESSAGES).collect::<Vec<_>>());
+    }
+    for pipe in &pipes {
+        std::fs::remove_file(pipe.path()).unwrap();
+    }
+}
+
+#[test]
+fn test_fan_in_times_out() {
+    let (pipe, reader, _writer) = ::test_pair("fan_in_timeout");
+    let mut fan_in = FanIn::new(vec![reader]).unwrap();
+    let err = fan_in.recv(Some(Duration::from_millis(20))).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(fan_in.active(), 1);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
@@ -0,0 +1,617 @@
+use std::io::{self, IoSlice, Read};
+use std::time::Duration;
+
+use core::reader::PipeReader;
+use core::writer::PipeWriter;
+use framing::{decode_frame_bytes, encode_header};
+
+/// chunk read from the pipe into the accumulation buffer per syscall
+const READ_CHUNK: usize = 64 * 1024;
+pub const CRC_LEN: usize = 4;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-32 as in zlib/PNG (IEEE polynomial, reflected), `crc32(b"123456789") == 0xcbf43926`
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc = CRC_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/**
+where `FramedPipe` gets the `Vec` for each received message
+
+`recv` calls `acquire` with the payload length, the caller hands the message back with `FramedPipe::release`
+once done with it, so a pool can recycle buffers instead of allocating for every message
+*/
+pub trait BufferPool {
+    /// an empty buffer with room for at least `min` bytes
+    fn acquire(&mut self, min: usize) -> Vec<u8>;
+    fn release(&mut self, buf: Vec<u8>);
+}
+
+/// the default, allocates a fresh buffer per message and drops released ones
+#[derive(Debug, Default)]
+pub struct HeapPool;
+
+impl BufferPool for HeapPool {
+    fn acquire(&mut self, min: usize) -> Vec<u8> {
+        Vec::with_capacity(min)
+    }
+
+    fn release(&mut self, _buf: Vec<u8>) {}
+}
+
+/// keeps up to `max_buffers` released buffers of `buf_size` capacity for reuse, bigger messages still allocate
+#[derive(Debug)]
+pub struct FixedSizePool {
+    free: Vec<Vec<u8>>,
+    buf_size: usize,
+    max_buffers: usize,
+}
+
+impl FixedSizePool {
+    pub fn new(buf_size: usize, max_buffers: usize) -> Self {
+        FixedSizePool {
+            free: Vec::with_capacity(max_buffers),
+            buf_size,
+            max_buffers,
+        }
+    }
+}
+
+impl BufferPool for FixedSizePool {
+    fn acquire(&mut self, min: usize) -> Vec<u8> {
+        if min <= self.buf_size {
+            if let Some(buf) = self.free.pop() {
+                return buf;
+            }
+        }
+        Vec::with_capacity(min.max(self.buf_size))
+    }
+
+    fn release(&mut self, mut buf: Vec<u8>) {
+        // a buffer which grew past buf_size is kept too, one which shrank below it is not
+        if buf.capacity() >= self.buf_size && self.free.len() < self.max_buffers {
+            buf.clear();
+            self.free.push(buf);
+        }
+    }
+}
+
+/**
+framed messages with an accumulation buffer, same wire format as `write_message`/`read_message`
+
+`FramedPipe<PipeReader>` reads big chunks and cuts frames out of its buffer instead of two reads per message,
+`FramedPipe<PipeWriter>` is the sending side
+*/
+#[derive(Debug)]
+pub struct FramedPipe<T, P = HeapPool> {
+    inner: T,
+    pool: P,
+    /// zero-filled storage, the unread bytes are `buf[start..end]`
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+    eof: bool,
+    /// a `send` failed, some bytes of its frame may be in the pipe already
+    partial: bool,
+}
+
+impl<T> FramedPipe<T> {
+    pub fn new(inner: T) -> Self {
+        FramedPipe::with_pool(inner, HeapPool)
+    }
+}
+
+impl<T, P: BufferPool> FramedPipe<T, P> {
+    pub fn with_pool(inner: T, pool: P) -> Self {
+        FramedPipe {
+            inner,
+            pool,
+            buf: Vec::new(),
+            start: 0,
+            end: 0,
+            eof: false,
+            partial: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// give a message returned by `recv`/`recv_many` back to the pool
+    pub fn release(&mut self, message: Vec<u8>) {
+        self.pool.release(message);
+    }
+}
+
+impl FramedPipe<PipeWriter> {
+    /// switch an fd used for raw writes so far to frames, at a point where the reader expects a frame header next
+    pub fn from_raw(writer: PipeWriter) -> Self {
+        FramedPipe::new(writer)
+    }
+
+    pub fn send(&mut self, payload: &[u8]) -> io::Result<()> {
+        // a payload refused here never touched the pipe
+        let header = encode_header(payload.len())?;
+        self.partial = true;
+        self.inner.write_all_vectored(&mut [IoSlice::new(&header), IoSlice::new(payload)])?;
+        self.partial = false;
+        Ok(())
+    }
+
+    /**
+    a frame whose payload ends with the big-endian `crc32` of `payload`, for `recv_checked`
+
+    length header and CRC live on the stack and go out with the payload in one writev, no concatenated copy.
+    Up to `PIPE_BUF` bytes in total (`payload.len() + 8`) that write is atomic, so frames of concurrent producers
+    never interleave; bigger ones are still sent whole but can be interleaved like any plain write
+    */
+    pub fn send_checked(&mut self, payload: &[u8]) -> io::Result<()> {
+        let header = encode_header(payload.len() + CRC_LEN)?;
+        let crc = crc32(payload).to_be_bytes();
+        self.partial = true;
+        self.inner
+            .write_all_vectored(&mut [IoSlice::new(&header), IoSlice::new(payload), IoSlice::new(&crc)])?;
+        self.partial = false;
+        Ok(())
+    }
+
+    /**
+    back to raw writes on the same fd, only possible right after a complete frame
+
+    after a failed `send` part of its frame may have gone out already and raw bytes would be read as the rest of it,
+    so that fails with `InvalidData`
+    */
+    pub fn into_raw(self) -> io::Result<PipeWriter> {
+        if self.partial {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the last send failed, the pipe may hold a partial frame",
+            ));
+        }
+        Ok(self.inner)
+    }
+}
+
+impl<P: BufferPool> FramedPipe<PipeReader, P> {
+    /// next frame, blocking until it's complete, `Ok(None)` at a clean EOF between frames
+    pub fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(frame) = self.next_buffered()? {
+                return Ok(Some(frame));
+            }
+            if self.eof || self.fill()? == 0 {
+                return self.eof_result();
+            }
+        }
+    }
+
+    /// next frame sent by `send_checked` with its CRC verified and stripped, `InvalidData` on a mismatch
+    pub fn recv_checked(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut frame = match self.recv()? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        if frame.len() < CRC_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame of {} bytes is too short for a CRC", frame.len()),
+            ));
+        }
+        let payload_len = frame.len() - CRC_LEN;
+        let expected = u32::from_be_bytes([
+            frame[payload_len],
+            frame[payload_len + 1],
+            frame[payload_len + 2],
+            frame[payload_len + 3],
+        ]);
+        let actual = crc32(&frame[..payload_len]);
+        if actual != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("CRC mismatch: frame says {:08x}, payload has {:08x}", expected, actual),
+            ));
+        }
+        frame.truncate(payload_len);
+        Ok(Some(frame))
+    }
+
+    /**
+    every complete frame available right now, at most `max` of them, possibly none
+
+    does at most one read and only when the pipe is readable, so it never blocks.
+    An empty result after the writer closed is reported by `is_eof`
+    */
+    pub fn recv_many(&mut self, max: usize) -> io::Result<Vec<Vec<u8>>> {
+        if !self.eof && self.inner.wait_readable(Some(Duration::from_secs(0)))? {
+            self.fill()?;
+        }
+        let mut frames = Vec::new();
+        while frames.len() < max {
+            match self.next_buffered()? {
+                Some(frame) => frames.push(frame),
+                None => break,
+            }
+        }
+        if frames.is_empty() && self.eof {
+            self.eof_result()?;
+        }
+        Ok(frames)
+    }
+
+    /// the writer closed and every complete frame has been handed out, what's left can only be part of one
+    pub fn is_eof(&self) -> bool {
+        self.eof && !matches!(decode_frame_bytes(&self.buf[self.start..self.end]), Ok(Some(_)))
+    }
+
+    /**
+    one read into the buffer, returns the bytes read
+
+    the frames handed out so far are dropped from the front first, the storage only grows (and gets zeroed) when
+    less than `READ_CHUNK` is left after the unread bytes
+    */
+    fn fill(&mut self) -> io::Result<usize> {
+        if self.start != 0 {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        if self.buf.len() - self.end < READ_CHUNK {
+            self.buf.resize(self.end + READ_CHUNK, 0);
+        }
+        let n = loop {
+            match self.inner.read(&mut self.buf[self.end..]) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                res => break res?,
+            }
+        };
+        self.end += n;
+        if n == 0 {
+            self.eof = true;
+        }
+        Ok(n)
+    }
+
+    fn next_buffered(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let (frame, consumed) = match decode_frame_bytes(&self.buf[self.start..self.end])? {
+            Some((payload, consumed)) => {
+                let mut frame = self.pool.acquire(payload.len());
+                frame.extend_from_slice(payload);
+                (frame, consumed)
+            }
+            None => return Ok(None),
+        };
+        self.start += consumed;
+        Ok(Some(frame))
+    }
+
+    /// EOF is clean only between frames
+    fn eof_result(&self) -> io::Result<Option<Vec<u8>>> {
+        if self.start == self.end {
+            Ok(None)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("pipe closed with {} bytes of an incomplete frame", self.end - self.start),
+            ))
+        }
+    }
+}
+
+#[test]
+fn test_recv_many_burst() {
+    let (pipe, reader, writer) = ::test_pair("recv_many");
+    let mut sender = FramedPipe::new(writer);
+    let mut receiver = FramedPipe::new(reader);
+    assert!(receiver.recv_many(10).unwrap().is_empty());
+    for i in 0..5u8 {
+        sender.send(&[i; 3]).unwrap();
+    }
+    let frames = receiver.recv_many(10).unwrap();
+    assert_eq!(frames, (0..5u8).map(|i| vec![i; 3]).collect::<Vec<_>>());
+    assert!(receiver.recv_many(10).unwrap().is_empty());
+
+    sender.send(b"last").unwrap();
+    drop(sender);
+    assert_eq!(receiver.recv().unwrap().unwrap(), b"last");
+    assert_eq!(receiver.recv().unwrap(), None);
+    assert!(receiver.is_eof());
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_is_eof_with_a_truncated_frame() {
+    use std::io::Write;
+
+    let (pipe, reader, mut writer) = ::test_pair("framed_truncated");
+    let mut receiver = FramedPipe::new(reader);
+    // a header announcing 10 bytes, only 3 of them follow
+    writer.write_all(&encode_header(10).unwrap()).unwrap();
+    writer.write_all(b"abc").unwrap();
+    drop(writer);
+    assert_eq!(receiver.recv().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    assert!(receiver.is_eof());
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_recv_many_respects_max() {
+    let (pipe, reader, writer) = ::test_pair("recv_many_max");
+    let mut sender = FramedPipe::new(writer);
+    let mut receiver = FramedPipe::new(reader);
+    for i in 0..5u8 {
+        sender.send(&[i]).unwrap();
+    }
+    assert_eq!(receiver.recv_many(3).unwrap().len(), 3);
+    // the other two are already buffered
+    assert_eq!(receiver.recv_many(3).unwrap(), vec![vec![3], vec![4]]);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_recv_reuses_read_buffer() {
+    let (pipe, reader, writer) = ::test_pair("framed_buffer_reuse");
+    let producer = std::thread::spawn(move || {
+        let mut sender = FramedPipe::new(writer);
+        for i in 0..5000u32 {
+            sender.send(&[i as u8; 100]).unwrap();
+        }
+    });
+    let mut receiver = FramedPipe::new(reader);
+    for i in 0..5000u32 {
+        assert_eq!(receiver.recv().unwrap().unwrap(), [i as u8; 100]);
+    }
+    assert_eq!(receiver.recv().unwrap(), None);
+    producer.join().unwrap();
+    // half a megabyte went through, the storage stayed at its first size plus at most one partial frame
+    assert!(receiver.buf.len() < 2 * READ_CHUNK, "buffer grew to {}", receiver.buf.len());
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_switch_between_framed_and_raw() {
+    use std::io::Write;
+
+    let (pipe, mut reader, writer) = ::test_pair("framed_into_raw");
+    let mut framed = FramedPipe::from_raw(writer);
+    framed.send(b"framed").unwrap();
+    let mut raw = framed.into_raw().unwrap();
+    raw.write_all(b"raw bytes").unwrap();
+    let mut framed = FramedPipe::from_raw(raw);
+    framed.send(b"framed again").unwrap();
+    drop(framed);
+
+    assert_eq!(reader.read_message().unwrap().unwrap(), b"framed");
+    let mut raw_bytes = [0u8; 9];
+    reader.read_exact(&mut raw_bytes).unwrap();
+    assert_eq!(&raw_bytes, b"raw bytes");
+    assert_eq!(reader.read_message().unwrap().unwrap(), b"framed again");
+    assert_eq!(reader.read_message().unwrap(), None);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_into_raw_after_failed_send() {
+    let (pipe, reader, writer) = ::test_pair("framed_into_raw_partial");
+    drop(reader);
+    let mut framed = FramedPipe::from_raw(writer);
+    assert_eq!(framed.send(b"lost").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    assert_eq!(framed.into_raw().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_into_raw_after_oversize_send() {
+    use std::io::Write;
+
+    let (pipe, mut reader, writer) = ::test_pair("framed_into_raw_oversize");
+    let mut framed = FramedPipe::from_raw(writer);
+    let oversize = vec![0u8; ::framing::MAX_MESSAGE_LEN + 1];
+    assert_eq!(framed.send(&oversize).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    let mut raw = framed.into_raw().unwrap();
+    raw.write_all(b"raw").unwrap();
+    drop(raw);
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, b"raw");
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_recv_recycles_pool_buffers() {
+    #[derive(Default)]
+    struct CountingPool {
+        free: Vec<Vec<u8>>,
+        allocated: usize,
+        reused: usize,
+    }
+
+    impl BufferPool for CountingPool {
+        fn acquire(&mut self, min: usize) -> Vec<u8> {
+            match self.free.pop() {
+                Some(buf) if buf.capacity() >= min => {
+                    self.reused += 1;
+                    buf
+                }
+                _ => {
+                    self.allocated += 1;
+                    Vec::with_capacity(min.max(64))
+                }
+            }
+        }
+
+        fn release(&mut self, mut buf: Vec<u8>) {
+            buf.clear();
+            self.free.push(buf);
+        }
+    }
+
+    let (pipe, reader, writer) = ::test_pair("framed_pool");
+    let mut sender = FramedPipe::new(writer);
+    let mut receiver = FramedPipe::with_pool(reader, CountingPool::default());
+    for i in 0..100u8 {
+        sender.send(&[i; 32]).unwrap();
+    }
+    drop(sender);
+    for i in 0..100u8 {
+        let message = receiver.recv().unwrap().unwrap();
+        assert_eq!(message, [i; 32]);
+        receiver.release(message);
+    }
+    assert_eq!(receiver.recv().unwrap(), None);
+    assert_eq!(receiver.pool.allocated, 1);
+    assert_eq!(receiver.pool.reused, 99);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_fixed_size_pool() {
+    let mut pool = FixedSizePool::new(128, 1);
+    let buf = pool.acquire(10);
+    assert!(buf.capacity() >= 128);
+    let ptr = buf.as_ptr();
+    pool.release(buf);
+    pool.release(Vec::with_capacity(128));
+    // max_buffers is 1, the second release was dropped
+    let reused = pool.acquire(100);
+    assert_eq!(reused.as_ptr(), ptr);
+    // too big for the pool, allocated on its own
+    assert!(pool.acquire(1000).capacity() >= 1000);
+}
+
+#[test]
+fn test_crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(b""), 0);
+}
+
+#[test]
+fn test_send_checked_concurrent_producers() {
+    use framing::HEADER_LEN;
+
+    const PRODUCERS: u8 = 4;
+    const MESSAGES: usize = 200;
+
+    let (pipe, reader, writer) = ::test_pair("send_checked");
+    let producers: Vec<_> = (0..PRODUCERS)
+        .map(|id| {
+            // opened before the first writer is dropped, else the reader could see EOF early
+            let mut sender = FramedPipe::new(pipe.open_writer().unwrap());
+            std::thread::spawn(move || {
+                for i in 0..MESSAGES {
+                    // every frame a different size, all of them within PIPE_BUF
+                    let payload = vec![id; 1 + (i * 37) % (libc::PIPE_BUF - HEADER_LEN - CRC_LEN - 1)];
+                    sender.send_checked(&payload).unwrap();
+                }
+            })
+        })
+        .collect();
+    drop(writer);
+    let mut receiver = FramedPipe::new(reader);
+    let mut received = [0usize; PRODUCERS as usize];
+    while let Some(payload) = receiver.recv_checked().unwrap() {
+        let id = payload[0];
+        assert!(payload.iter().all(|&b| b == id), "interleaved frame from producer {}", id);
+        received[id as usize] += 1;
+    }
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    assert_eq!(received, [MESSAGES; PRODUCERS as usize]);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_recv_checked_rejects_corruption() {
+    let (pipe, reader, writer) = ::test_pair("recv_checked_corrupt");
+    let mut sender = FramedPipe::new(writer);
+    let mut receiver = FramedPipe::new(reader);
+    sender.send(b"no crc at all").unwrap();
+    assert_eq!(receiver.recv_checked().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    sender.send_checked(b"fine").unwrap();
+    assert_eq!(receiver.recv_checked().unwrap().unwrap(), b"fine");
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_recv_checked_detects_flipped_byte() {
+    use std::io::Write;
+
+    use framing::HEADER_LEN;
+
+    let (pipe, reader, mut writer) = ::test_pair("recv_checked_flipped");
+    let mut frame = encode_header(b"payload".len() + CRC_LEN).unwrap().to_vec();
+    frame.extend_from_slice(b"payload");
+    frame.extend_from_slice(&crc32(b"payload").to_be_bytes());
+    frame[HEADER_LEN + 3] ^= 0x01;
+    writer.write_all(&frame).unwrap();
+    let mut receiver = FramedPipe::new(reader);
+    let err = receiver.recv_checked().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("CRC mismatch"), "{}", err);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+/// a reader thread draining the pipe, so the bench iterations never block on a full pipe
+#[cfg(test)]
+fn bench_sink(tag: &str) -> (::NamedPipe, PipeWriter, std::thread::JoinHandle<()>) {
+    let (pipe, mut reader, writer) = ::test_pair(tag);
+    let drain = std::thread::spawn(move || {
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+    });
+    (pipe, writer, drain)
+}
+
+#[cfg(test)]
+#[bench]
+fn bench_send_concat_then_write(b: &mut ::test::Bencher) {
+    use std::io::Write;
+
+    use framing::HEADER_LEN;
+
+    let (pipe, mut writer, drain) = bench_sink("bench_send_concat");
+    let payload = [7u8; 512];
+    b.iter(|| {
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload.len() + CRC_LEN);
+        frame.extend_from_slice(&((payload.len() + CRC_LEN) as u32).to_be_bytes());
+        frame.extend_from_slice(&payload);
+        frame.extend_from_slice(&crc32(&payload).to_be_bytes());
+        writer.write_all(&frame).unwrap();
+    });
+    drop(writer);
+    drain.join().unwrap();
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[cfg(test)]
+#[bench]
+fn bench_send_checked(b: &mut ::test::Bencher) {
+    let (pipe, writer, drain) = bench_sink("bench_send_checked");
+    let mut sender = FramedPipe::new(writer);
+    let payload = [7u8; 512];
+    b.iter(|| sender.send_checked(&payload).unwrap());
+    drop(sender);
+    drain.join().unwrap();
+    std::fs::remove_file(pipe.path()).unwrap();
+}
@@ -0,0 +1,210 @@
+/*!
+length-prefixed messages over a byte stream
+
+every frame is a 4 byte big-endian payload length followed by the payload,
+big-endian so readers written in other languages don't have to guess the host byte order
+
+wire format, all integers big-endian whatever the host is:
+
+| offset | size | field                                          |
+|--------|------|------------------------------------------------|
+| 0      | 4    | payload length `n`, at most `MAX_MESSAGE_LEN`  |
+| 4      | `n`  | payload                                        |
+
+the layers on top put their header at the start of the payload:
+
+| layer       | payload starts with                                        |
+|-------------|------------------------------------------------------------|
+| `seqno`     | 8 byte sequence number                                     |
+| `multiplex` | 4 byte channel id, 1 byte flags (`1` = channel closed)     |
+
+`FramedPipe::send_checked` frames end with the 4 byte `crc32` of the rest of the payload instead
+
+`send_typed` messages put 1 byte version and 2 byte tag in front of the whole frame, `write_message_synced`
+messages a 4 byte sync marker
+*/
+use std::io::{self, IoSlice, Read};
+
+use core::reader::PipeReader;
+use core::writer::PipeWriter;
+
+pub const HEADER_LEN: usize = 4;
+/// frames above this are refused on both sides, a garbage length shouldn't make the reader allocate gigabytes
+pub const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+pub(crate) fn check_len(len: usize) -> io::Result<()> {
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes exceeds MAX_MESSAGE_LEN {}", len, MAX_MESSAGE_LEN),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn encode_header(len: usize) -> io::Result<[u8; HEADER_LEN]> {
+    check_len(len)?;
+    Ok((len as u32).to_be_bytes())
+}
+
+/**
+parse one frame from the start of `bytes` without any I/O, for readers which get the bytes on their own
+
+`Ok(Some((payload, consumed)))` where `consumed` is header plus payload length, `Ok(None)` if `bytes` holds
+only part of a frame so far, `InvalidData` for a length above `MAX_MESSAGE_LEN`
+*/
+pub fn decode_frame_bytes(bytes: &[u8]) -> io::Result<Option<(&[u8], usize)>> {
+    if bytes.len() < HEADER_LEN {
+        return Ok(None);
+    }
+    let len = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    check_len(len)?;
+    if bytes.len() < HEADER_LEN + len {
+        return Ok(None);
+    }
+    Ok(Some((&bytes[HEADER_LEN..HEADER_LEN + len], HEADER_LEN + len)))
+}
+
+/// read exactly `buf.len()` bytes, `Ok(false)` if EOF came before the first byte
+pub(crate) fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("pipe closed after {} of {} bytes", filled, buf.len()),
+                ))
+            }
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+impl PipeWriter {
+    pub fn write_message(&mut self, payload: &[u8]) -> io::Result<()> {
+        let header = encode_header(payload.len())?;
+        // header and payload in one writev, no concatenated copy
+        self.write_all_vectored(&mut [IoSlice::new(&header), IoSlice::new(payload)])
+    }
+}
+
+impl PipeReader {
+    /// next frame, `Ok(None)` when the writer closed cleanly between frames
+    pub fn read_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut payload = Vec::new();
+        Ok(self.read_message_into(&mut payload)?.map(|_| payload))
+    }
+
+    /**
+    `read_message` into the caller's buffer, returns the payload length
+
+    `buf` is cleared and then holds just the payload, its capacity is kept so a buffer reused across calls
+    only allocates when a message is bigger than every one before
+    */
+    pub fn read_message_into(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<usize>> {
+        buf.clear();
+        let mut header = [0u8; HEADER_LEN];
+        if !read_exact_or_eof(self, &mut header)? {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes(header) as usize;
+        check_len(len)?;
+        buf.resize(len, 0);
+        if !read_exact_or_eof(self, buf)? {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "pipe closed inside a message"));
+        }
+        Ok(Some(len))
+    }
+}
+
+#[test]
+fn test_message_roundtrip() {
+    let (pipe, mut reader, mut writer) = ::test_pair("message_roundtrip");
+    writer.write_message(b"hello").unwrap();
+    writer.write_message(b"").unwrap();
+    writer.write_message(&[7u8; 10_000]).unwrap();
+    drop(writer);
+    assert_eq!(reader.read_message().unwrap().unwrap(), b"hello");
+    assert_eq!(reader.read_message().unwrap().unwrap(), b"");
+    assert_eq!(reader.read_message().unwrap().unwrap(), vec![7u8; 10_000]);
+    assert_eq!(reader.read_message().unwrap(), None);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_message_truncated() {
+    use std::io::Write;
+
+    let (pipe, mut reader, mut writer) = ::test_pair("message_truncated");
+    writer.write_all(&[0, 0, 0, 5, b'h', b'i']).unwrap();
+    drop(writer);
+    assert_eq!(reader.read_message().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_zero_read_is_eof() {
+    let (pipe, mut reader, mut writer) = ::test_pair("zero_read_eof");
+    writer.write_message(b"last").unwrap();
+    drop(writer);
+    assert_eq!(reader.read_message().unwrap().unwrap(), b"last");
+    assert_eq!(reader.read_message().unwrap(), None);
+    let mut buf = [0u8; 16];
+    assert_eq!(reader.read_timeout(&mut buf, std::time::Duration::from_secs(1)).unwrap(), 0);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_decode_frame_bytes() {
+    let bytes = [0, 0, 0, 2, b'h', b'i', 0, 0, 0, 0, 0xff];
+    assert_eq!(decode_frame_bytes(&bytes).unwrap(), Some((&b"hi"[..], 6)));
+    // an empty payload right after
+    assert_eq!(decode_frame_bytes(&bytes[6..]).unwrap(), Some((&b""[..], 4)));
+    // truncated header and truncated payload both want more bytes
+    assert_eq!(decode_frame_bytes(&bytes[..3]).unwrap(), None);
+    assert_eq!(decode_frame_bytes(&bytes[..5]).unwrap(), None);
+    assert_eq!(decode_frame_bytes(&[]).unwrap(), None);
+    assert_eq!(
+        decode_frame_bytes(&[0xff, 0xff, 0xff, 0xff]).unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+}
+
+#[test]
+fn test_golden_wire_format() {
+    let (pipe, mut reader, mut writer) = ::test_pair("golden_wire");
+    writer.write_message(b"hi").unwrap();
+    drop(writer);
+    let mut wire = Vec::new();
+    reader.read_to_end(&mut wire).unwrap();
+    assert_eq!(wire, [0x00, 0x00, 0x00, 0x02, 0x68, 0x69]);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_read_message_into_reuses_buffer() {
+    let (pipe, mut reader, mut writer) = ::test_pair("read_message_into");
+    // the largest first, it's the only one which may grow the buffer
+    let messages: Vec<Vec<u8>> = (0..10u8).rev().map(|i| vec![i; 100 + i as usize * 10]).collect();
+    for message in &messages {
+        writer.write_message(message).unwrap();
+    }
+    drop(writer);
+    let mut buf = Vec::new();
+    assert_eq!(reader.read_message_into(&mut buf).unwrap(), Some(messages[0].len()));
+    assert_eq!(buf, messages[0]);
+    let before = ::alloc_count::allocations();
+    for message in &messages[1..] {
+        assert_eq!(reader.read_message_into(&mut buf).unwrap(), Some(message.len()));
+        assert_eq!(&buf, message);
+    }
+    assert_eq!(reader.read_message_into(&mut buf).unwrap(), None);
+    assert!(buf.is_empty());
+    assert_eq!(::alloc_count::allocations(), before);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
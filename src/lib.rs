@@ -24,53 +24,164 @@ YouTube录像: https://youtu.be/O0jOXlkMBLM
 pipe/FIFO文件是「橙黄色」
 */
 #![feature(rustc_private)]
+#![cfg_attr(test, feature(test))]
 extern crate libc;
+#[cfg(test)]
+extern crate test;
+
+/// the bare build exports `NamedPipe`, `Pipe`, `raw_mkfifo`, `PipeReader`, `PipeWriter`, `PipeError` and
+/// `errno_to_error`, every helper on top needs its feature:
+#[cfg_attr(not(feature = "buffered"), doc = "```compile_fail,E0432\nuse rust_pipe::BufPipeReader;\n```")]
+#[cfg_attr(not(feature = "buffered"), doc = "```compile_fail,E0599\nfn f(reader: rust_pipe::PipeReader) { reader.buffered(); }\n```")]
+#[cfg_attr(not(feature = "buffered"), doc = "```compile_fail,E0599\nfn f(writer: &mut rust_pipe::PipeWriter) { writer.write_cstr(\"x\").unwrap(); }\n```")]
+#[cfg_attr(not(feature = "capacity"), doc = "```compile_fail,E0432\nuse rust_pipe::CapacityGrant;\n```")]
+#[cfg_attr(not(feature = "capacity"), doc = "```compile_fail,E0599\nfn f(writer: &rust_pipe::PipeWriter) { writer.capacity().unwrap(); }\n```")]
+#[cfg_attr(not(feature = "inspect"), doc = "```compile_fail,E0432\nuse rust_pipe::PathKind;\n```")]
+#[cfg_attr(not(feature = "inspect"), doc = "```compile_fail,E0432\nuse rust_pipe::errno_name;\n```")]
+#[cfg_attr(not(feature = "inspect"), doc = "```compile_fail,E0599\nrust_pipe::NamedPipe::new(\"/tmp/x\").path_kind().unwrap();\n```")]
+#[cfg_attr(not(feature = "packet"), doc = "```compile_fail,E0599\nrust_pipe::NamedPipe::new(\"/tmp/x\").open_packet_writer().unwrap();\n```")]
+#[cfg_attr(not(feature = "poll"), doc = "```compile_fail,E0432\nuse rust_pipe::PollFlags;\n```")]
+#[cfg_attr(not(feature = "secure"), doc = "```compile_fail,E0599\nrust_pipe::NamedPipe::create_secure(\"/tmp\", \"x\", 0o600).unwrap();\n```")]
+#[cfg_attr(not(feature = "stats"), doc = "```compile_fail,E0432\nuse rust_pipe::WriteStats;\n```")]
+#[cfg_attr(not(feature = "stats"), doc = "```compile_fail,E0599\nfn f(writer: &rust_pipe::PipeWriter) { writer.stats(); }\n```")]
+#[cfg_attr(not(feature = "stdio"), doc = "```compile_fail,E0599\nrust_pipe::NamedPipe::new(\"/tmp/x\").as_stdio_read().unwrap();\n```")]
+#[cfg_attr(not(feature = "framing"), doc = "```compile_fail,E0432\nuse rust_pipe::Multiplexer;\n```")]
+#[cfg_attr(not(feature = "framing"), doc = "```compile_fail,E0432\nuse rust_pipe::FramedPipe;\n```")]
+#[cfg_attr(not(feature = "clock"), doc = "```compile_fail,E0432\nuse rust_pipe::Clock;\n```")]
+#[cfg_attr(not(feature = "clock"), doc = "```compile_fail,E0432\nuse rust_pipe::MockClock;\n```")]
+#[cfg_attr(not(feature = "clock"), doc = "```compile_fail,E0432\nuse rust_pipe::SystemClock;\n```")]
+#[cfg_attr(not(feature = "config"), doc = "```compile_fail,E0432\nuse rust_pipe::NamedPipeConfig;\n```")]
+#[cfg_attr(not(feature = "duplex"), doc = "```compile_fail,E0432\nuse rust_pipe::Duplex;\n```")]
+#[cfg_attr(not(feature = "duplex"), doc = "```compile_fail,E0432\nuse rust_pipe::PipeDuplexEnd;\n```")]
+#[cfg_attr(not(feature = "lifecycle"), doc = "```compile_fail,E0432\nuse rust_pipe::PipeLifecycle;\n```")]
+#[cfg_attr(not(feature = "lifecycle"), doc = "```compile_fail,E0432\nuse rust_pipe::OwnedPipe;\n```")]
+#[cfg_attr(not(feature = "lifecycle"), doc = "```compile_fail,E0432\nuse rust_pipe::OpenWatchdog;\n```")]
+#[cfg_attr(not(feature = "report"), doc = "```compile_fail,E0432\nuse rust_pipe::PipeReport;\n```")]
+#[cfg_attr(not(feature = "retry"), doc = "```compile_fail,E0432\nuse rust_pipe::RetryPolicy;\n```")]
+#[cfg_attr(not(feature = "retry"), doc = "```compile_fail,E0432\nuse rust_pipe::SpinPolicy;\n```")]
+#[cfg_attr(not(feature = "retry"), doc = "```compile_fail,E0432\nuse rust_pipe::ReconnectingWriter;\n```")]
+#[cfg_attr(not(feature = "select"), doc = "```compile_fail,E0432\nuse rust_pipe::PipeSelector;\n```")]
+#[cfg_attr(not(feature = "select"), doc = "```compile_fail,E0432\nuse rust_pipe::ShutdownReader;\n```")]
+#[cfg_attr(not(feature = "signals"), doc = "```compile_fail,E0432\nuse rust_pipe::ignore_sigpipe;\n```")]
+#[cfg_attr(not(feature = "signals"), doc = "```compile_fail,E0432\nuse rust_pipe::SigPipeGuard;\n```")]
+#[cfg_attr(not(feature = "splice"), doc = "```compile_fail,E0432\nuse rust_pipe::splice_between;\n```")]
+#[cfg_attr(not(feature = "splice"), doc = "```compile_fail,E0432\nuse rust_pipe::TeeReader;\n```")]
+/// ```
+/// use rust_pipe::{errno_to_error, raw_mkfifo, NamedPipe, Pipe, PipeError, PipeReader, PipeWriter};
+/// ```
+mod core;
+#[cfg(feature = "framing")]
+mod broadcast;
+#[cfg(feature = "framing")]
+mod chunked;
+#[cfg(feature = "framing")]
+mod fanin;
+#[cfg(feature = "framing")]
+mod framed;
+#[cfg(feature = "framing")]
+mod framing;
+#[cfg(feature = "framing")]
+mod message;
+#[cfg(feature = "framing")]
+mod multiplex;
+#[cfg(feature = "framing")]
+mod ping;
+#[cfg(feature = "framing")]
+mod reliable;
+#[cfg(feature = "framing")]
+mod resync;
+#[cfg(feature = "framing")]
+mod seqno;
+pub use core::*;
+#[cfg(feature = "framing")]
+pub use broadcast::{Broadcaster, OverflowPolicy};
+#[cfg(feature = "framing")]
+pub use chunked::{ChunkedReader, ChunkedWriter};
+#[cfg(feature = "framing")]
+pub use fanin::FanIn;
+#[cfg(feature = "framing")]
+pub use framed::{crc32, BufferPool, FixedSizePool, FramedPipe, HeapPool, CRC_LEN};
+#[cfg(feature = "framing")]
+pub use framing::{decode_frame_bytes, MAX_MESSAGE_LEN};
+#[cfg(feature = "framing")]
+pub use message::MESSAGE_VERSION;
+#[cfg(feature = "framing")]
+pub use multiplex::{Demultiplexer, Multiplexer};
+#[cfg(feature = "framing")]
+pub use reliable::ReliableDuplex;
+#[cfg(feature = "framing")]
+pub use resync::SYNC_MAGIC;
+#[cfg(feature = "framing")]
+pub use seqno::{SequencedReader, SequencedWriter};
+
+use core::errno::{errno_to_err_msg, last_errno};
+use core::sys::path_to_cstring;
 
+#[allow(dead_code)]
+const PATH: &str = "/home/w/temp/my_pipe";
 
-#[cfg(any(target_os = "freebsd",
-                 target_os = "ios",
-                 target_os = "macos"))] 
-        unsafe fn errno_location() -> *mut libc::c_int {
-            libc::__error()
-        }
- #[cfg(any(target_os = "android",
-                        target_os = "netbsd",
-                        target_os = "openbsd"))] 
-        unsafe fn errno_location() -> *mut libc::c_int {
-            libc::__errno()
-        }
- #[cfg(any(target_os = "linux"))] 
-        unsafe fn errno_location() -> *mut libc::c_int {
-            libc::__errno_location()
+/// a fresh FIFO under the temp dir, unique per test process and tag
+#[cfg(test)]
+fn test_pipe(tag: &str) -> NamedPipe {
+    let path = std::env::temp_dir().join(format!("rust_pipe_{}_{}", std::process::id(), tag));
+    let _ = std::fs::remove_file(&path);
+    let pipe = NamedPipe::new(path);
+    pipe.create(0o600).unwrap();
+    pipe
+}
+
+/// both ends of a fresh FIFO, the reader is opened on another thread since each open blocks for its peer
+#[cfg(test)]
+fn test_pair(tag: &str) -> (NamedPipe, PipeReader, PipeWriter) {
+    let pipe = test_pipe(tag);
+    let reader = {
+        let pipe = pipe.clone();
+        std::thread::spawn(move || pipe.open_reader().unwrap())
+    };
+    let writer = pipe.open_writer().unwrap();
+    (pipe, reader.join().unwrap(), writer)
+}
+
+/// counts allocations per thread, so tests running in parallel don't see each other's
+#[cfg(test)]
+mod alloc_count {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    struct CountingAlloc;
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            System.alloc(layout)
         }
-#[cfg(any(target_os = "illumos", target_os = "solaris"))] 
-        unsafe fn errno_location() -> *mut libc::c_int {
-            libc::___errno()
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
         }
 
-#[allow(dead_code)]
-const PATH: &str = "/home/w/temp/my_pipe";
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            System.realloc(ptr, layout, new_size)
+        }
+    }
 
-fn errno_to_err_msg(errno: i32) -> String {
-    let err_msg_buf  = [0u8; 128];
-    unsafe { libc::strerror_r(errno, err_msg_buf.as_ptr() as _,128) };
-    let err_msg_buf_len = err_msg_buf.iter().position(|&x| x == b'\0').unwrap();
-    let err_msg = unsafe { String::from_utf8_unchecked(err_msg_buf[..err_msg_buf_len].to_vec()) };
-    dbg!(std::io::Error::last_os_error());
-    dbg!(errno, &err_msg);
-    err_msg
-}
+    #[global_allocator]
+    static GLOBAL: CountingAlloc = CountingAlloc;
 
-#[test]
-fn test_errno_no_such_file_or_directory() {
-    let fd = unsafe { libc::open("/tmp/not_exist_file\0".as_ptr() as _, libc::O_RDONLY) };
-    let errno = unsafe { *errno_location() };
-    dbg!(fd, errno_to_err_msg(errno));
+    pub(crate) fn allocations() -> usize {
+        ALLOCATIONS.with(|n| n.get())
+    }
 }
 
 #[allow(dead_code)]
 fn my_mkfifo() {
-    let path_with_nul = format!("{}\0", PATH);
+    // not format!("{}\0", PATH), a NUL inside PATH would silently cut the path short
+    let path_with_nul = path_to_cstring(std::path::Path::new(PATH)).unwrap();
     if std::path::Path::new(PATH).exists() {
         // or use std::fs::File::metadata(&self)
         let mut file_stat = unsafe {std::mem::zeroed::<libc::stat>()};
@@ -83,9 +194,9 @@ fn my_mkfifo() {
     // https://users.rust-lang.org/t/named-pipes-in-rust/14721
     // https://docs.rs/nix/0.21.0/nix/unistd/fn.mkfifo.html
     // permission bit: https://www.gnu.org/software/libc/manual/html_node/Permission-Bits.html
-    let mkfifo_res = unsafe { libc::mkfifo(PATH.as_ptr() as _, libc::S_IREAD | libc::S_IWRITE) };
+    let mkfifo_res = unsafe { libc::mkfifo(path_with_nul.as_ptr(), libc::S_IREAD | libc::S_IWRITE) };
     if mkfifo_res == -1 {
-        let err_msg = errno_to_err_msg(unsafe { *errno_location() });
+        let err_msg = errno_to_err_msg(last_errno());
         panic!("syscall error = {}", err_msg);
     }
 }
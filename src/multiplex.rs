@@ -0,0 +1,168 @@
+/*!
+several logical channels over one FIFO
+
+each channel message is an ordinary frame whose body starts with a 4 byte big-endian channel id and a flags byte
+*/
+use std::collections::{HashSet, VecDeque};
+use std::io;
+
+use core::reader::PipeReader;
+use core::writer::PipeWriter;
+
+const CHANNEL_HEADER_LEN: usize = 5;
+/// control frame: the channel is finished, no payload
+const FLAG_CLOSE: u8 = 0x01;
+
+/// writing side, tags every message with its channel id
+#[derive(Debug)]
+pub struct Multiplexer {
+    writer: PipeWriter,
+    closed: HashSet<u32>,
+}
+
+impl Multiplexer {
+    pub fn new(writer: PipeWriter) -> Self {
+        Multiplexer {
+            writer,
+            closed: HashSet::new(),
+        }
+    }
+
+    pub fn send(&mut self, channel: u32, payload: &[u8]) -> io::Result<()> {
+        if self.closed.contains(&channel) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("channel {} is closed", channel),
+            ));
+        }
+        self.write_frame(channel, 0, payload)
+    }
+
+    /// tell the reader `channel` is done, the other channels keep flowing
+    pub fn close_channel(&mut self, channel: u32) -> io::Result<()> {
+        if !self.closed.insert(channel) {
+            return Ok(());
+        }
+        self.write_frame(channel, FLAG_CLOSE, &[])
+    }
+
+    pub fn into_inner(self) -> PipeWriter {
+        self.writer
+    }
+
+    fn write_frame(&mut self, channel: u32, flags: u8, payload: &[u8]) -> io::Result<()> {
+        let mut body = Vec::with_capacity(CHANNEL_HEADER_LEN + payload.len());
+        body.extend_from_slice(&channel.to_be_bytes());
+        body.push(flags);
+        body.extend_from_slice(payload);
+        self.writer.write_message(&body)
+    }
+}
+
+/// reading side, queues messages of the channels nobody asked for yet
+#[derive(Debug)]
+pub struct Demultiplexer {
+    reader: PipeReader,
+    /// in arrival order across all channels
+    pending: VecDeque<(u32, Vec<u8>)>,
+    closed: HashSet<u32>,
+    eof: bool,
+}
+
+impl Demultiplexer {
+    pub fn new(reader: PipeReader) -> Self {
+        Demultiplexer {
+            reader,
+            pending: VecDeque::new(),
+            closed: HashSet::new(),
+            eof: false,
+        }
+    }
+
+    /// next message of any channel in arrival order, `Ok(None)` at EOF of the FIFO
+    pub fn recv(&mut self) -> io::Result<Option<(u32, Vec<u8>)>> {
+        if let Some(message) = self.pending.pop_front() {
+            return Ok(Some(message));
+        }
+        loop {
+            match self.read_frame()? {
+                Some((channel, Some(payload))) => return Ok(Some((channel, payload))),
+                Some((_, None)) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// next message of `channel`, `Ok(None)` once it was closed by the writer (or the FIFO hit EOF)
+    pub fn recv_channel(&mut self, channel: u32) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(payload) = self.pop(channel) {
+                return Ok(Some(payload));
+            }
+            if self.closed.contains(&channel) || self.eof {
+                return Ok(None);
+            }
+            match self.read_frame()? {
+                Some((got, Some(payload))) if got == channel => return Ok(Some(payload)),
+                Some((got, Some(payload))) => self.pending.push_back((got, payload)),
+                Some((_, None)) | None => {}
+            }
+        }
+    }
+
+    pub fn is_closed(&self, channel: u32) -> bool {
+        self.closed.contains(&channel)
+    }
+
+    fn pop(&mut self, channel: u32) -> Option<Vec<u8>> {
+        let index = self.pending.iter().position(|&(got, _)| got == channel)?;
+        self.pending.remove(index).map(|(_, payload)| payload)
+    }
+
+    /// `Some((channel, None))` is a close frame, `None` EOF
+    fn read_frame(&mut self) -> io::Result<Option<(u32, Option<Vec<u8>>)>> {
+        let body = match self.reader.read_message()? {
+            Some(body) => body,
+            None => {
+                self.eof = true;
+                return Ok(None);
+            }
+        };
+        if body.len() < CHANNEL_HEADER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("multiplexed frame of {} bytes has no channel header", body.len()),
+            ));
+        }
+        let channel = u32::from_be_bytes([body[0], body[1], body[2], body[3]]);
+        if body[4] & FLAG_CLOSE != 0 {
+            self.closed.insert(channel);
+            return Ok(Some((channel, None)));
+        }
+        Ok(Some((channel, Some(body[CHANNEL_HEADER_LEN..].to_vec()))))
+    }
+}
+
+#[test]
+fn test_close_channel_mid_stream() {
+    let (pipe, reader, writer) = ::test_pair("multiplex_close");
+    let mut mux = Multiplexer::new(writer);
+    mux.send(1, b"one-a").unwrap();
+    mux.send(2, b"two-a").unwrap();
+    mux.close_channel(1).unwrap();
+    mux.send(2, b"two-b").unwrap();
+    assert_eq!(mux.send(1, b"late").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    mux.send(2, b"two-c").unwrap();
+    drop(mux);
+
+    let mut demux = Demultiplexer::new(reader);
+    assert_eq!(demux.recv_channel(1).unwrap().unwrap(), b"one-a");
+    assert_eq!(demux.recv_channel(1).unwrap(), None);
+    assert!(demux.is_closed(1));
+    assert_eq!(demux.recv_channel(1).unwrap(), None);
+    assert_eq!(demux.recv_channel(2).unwrap().unwrap(), b"two-a");
+    assert_eq!(demux.recv_channel(2).unwrap().unwrap(), b"two-b");
+    assert_eq!(demux.recv().unwrap(), Some((2, b"two-c".to_vec())));
+    assert_eq!(demux.recv().unwrap(), None);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
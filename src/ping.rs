@@ -0,0 +1,121 @@
+/*!
+liveness probe over a `Duplex` using framed messages
+
+ping and pong are ordinary frames with a reserved 5 byte prefix and an 8 byte big-endian nonce. Application
+frames starting with `\xffPING`/`\xffPONG` would be taken for control frames, so don't send those
+*/
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use core::duplex::Duplex;
+
+const PING: &[u8; 5] = b"\xffPING";
+const PONG: &[u8; 5] = b"\xffPONG";
+const CONTROL_LEN: usize = 5 + 8;
+
+static NEXT_NONCE: AtomicUsize = AtomicUsize::new(0);
+
+fn control_frame(kind: &[u8; 5], nonce: u64) -> [u8; CONTROL_LEN] {
+    let mut frame = [0u8; CONTROL_LEN];
+    frame[..5].copy_from_slice(kind);
+    frame[5..].copy_from_slice(&nonce.to_be_bytes());
+    frame
+}
+
+/// the nonce if `frame` is a control frame of `kind`
+fn parse_control(kind: &[u8; 5], frame: &[u8]) -> Option<u64> {
+    if frame.len() != CONTROL_LEN || &frame[..5] != kind {
+        return None;
+    }
+    let mut nonce = [0u8; 8];
+    nonce.copy_from_slice(&frame[5..]);
+    Some(u64::from_be_bytes(nonce))
+}
+
+impl Duplex {
+    /**
+    next application frame, answering every ping which arrives before it, `Ok(None)` at EOF
+
+    the peer's `ping` only gets its pong while this side sits in here, so this is the read loop of the answering side,
+    a plain `read_message` hands pings out like any other frame
+    */
+    pub fn recv_answering_pings(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let frame = match self.read_message()? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+            match parse_control(PING, &frame) {
+                Some(nonce) => self.writer()?.write_message(&control_frame(PONG, nonce))?,
+                None => return Ok(Some(frame)),
+            }
+        }
+    }
+
+    /**
+    send a ping and wait for its pong, returns the round-trip time or `TimedOut`
+
+    meant for a quiet duplex: an application frame arriving meanwhile fails the ping with `InvalidData` since it
+    can't be put back, a late pong of an earlier ping is skipped
+    */
+    pub fn ping(&mut self, timeout: Duration) -> io::Result<Duration> {
+        let nonce = NEXT_NONCE.fetch_add(1, Ordering::Relaxed) as u64;
+        let start = Instant::now();
+        let deadline = start + timeout;
+        self.writer()?.write_message(&control_frame(PING, nonce))?;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) || !self.reader_mut().wait_readable(Some(remaining))? {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("no pong within {:?}", timeout),
+                ));
+            }
+            let frame = match self.reader_mut().read_message()? {
+                Some(frame) => frame,
+                None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "peer closed before the pong")),
+            };
+            match parse_control(PONG, &frame) {
+                Some(got) if got == nonce => return Ok(start.elapsed()),
+                Some(_) => continue,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} byte application frame arrived while waiting for a pong", frame.len()),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_ping_rtt() {
+    let (a_to_b, b_to_a, mut a, mut b) = ::core::duplex::test_duplex("ping");
+    let responder = std::thread::spawn(move || {
+        let request = b.recv_answering_pings().unwrap().unwrap();
+        b.send(&request).unwrap();
+        // answers pings until the other side closes
+        assert_eq!(b.recv_answering_pings().unwrap(), None);
+    });
+    a.send(b"echo").unwrap();
+    assert_eq!(a.read_message().unwrap().unwrap(), b"echo");
+    for _ in 0..3 {
+        let rtt = a.ping(Duration::from_secs(5)).unwrap();
+        assert!(rtt > Duration::from_secs(0) && rtt < Duration::from_secs(5), "{:?}", rtt);
+    }
+    drop(a);
+    responder.join().unwrap();
+    std::fs::remove_file(a_to_b.path()).unwrap();
+    std::fs::remove_file(b_to_a.path()).unwrap();
+}
+
+#[test]
+fn test_ping_times_out_without_responder() {
+    let (a_to_b, b_to_a, mut a, _b) = ::core::duplex::test_duplex("ping_timeout");
+    let err = a.ping(Duration::from_millis(20)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    std::fs::remove_file(a_to_b.path()).unwrap();
+    std::fs::remove_file(b_to_a.path()).unwrap();
+}
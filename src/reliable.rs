@@ -0,0 +1,203 @@
+/*!
+at-least-once delivery over a `Duplex`: every message is acknowledged and resent until it is
+
+data frames are `\xffRDAT`, an 8 byte big-endian seqno and the payload, the ACK for one is `\xffRACK` and the same seqno.
+`recv_reliable` answers every data frame with an ACK and drops the retransmits it already delivered, so the application
+sees each message exactly once.
+
+the seqnos and the delivered state only live in memory: after a restart of either process the other side can't tell
+a resent message from a new one, nothing here survives that
+*/
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+
+use core::duplex::Duplex;
+use core::retry::RetryPolicy;
+use seqno::SEQNO_LEN;
+
+const DATA: &[u8; 5] = b"\xffRDAT";
+const ACK: &[u8; 5] = b"\xffRACK";
+const PREFIX_LEN: usize = 5;
+
+fn encode(kind: &[u8; 5], seqno: u64, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(PREFIX_LEN + SEQNO_LEN + payload.len());
+    frame.extend_from_slice(kind);
+    frame.extend_from_slice(&seqno.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// kind, seqno and payload of a frame of this protocol
+fn decode(frame: &[u8]) -> io::Result<(&[u8], u64, &[u8])> {
+    if frame.len() < PREFIX_LEN + SEQNO_LEN || (&frame[..PREFIX_LEN] != DATA && &frame[..PREFIX_LEN] != ACK) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} byte frame is neither data nor ACK", frame.len()),
+        ));
+    }
+    let mut seqno = [0u8; SEQNO_LEN];
+    seqno.copy_from_slice(&frame[PREFIX_LEN..PREFIX_LEN + SEQNO_LEN]);
+    Ok((&frame[..PREFIX_LEN], u64::from_be_bytes(seqno), &frame[PREFIX_LEN + SEQNO_LEN..]))
+}
+
+/// `Duplex` whose messages are acknowledged by the peer, both sides have to use it
+#[derive(Debug)]
+pub struct ReliableDuplex {
+    duplex: Duplex,
+    /// per attempt, how long `send_reliable` waits for the ACK
+    ack_timeout: Duration,
+    policy: RetryPolicy,
+    next: u64,
+    /// highest seqno handed to the application, anything at or below is a retransmit
+    delivered: Option<u64>,
+    /// data frames which arrived while `send_reliable` waited for its ACK
+    pending: VecDeque<Vec<u8>>,
+    /// swallow every n-th ACK instead of sending it, 0 never
+    #[cfg(test)]
+    drop_ack_every: u64,
+    #[cfg(test)]
+    acks: u64,
+}
+
+impl ReliableDuplex {
+    /// `policy` decides how often a message whose ACK didn't come within `ack_timeout` is resent
+    pub fn new(duplex: Duplex, ack_timeout: Duration, policy: RetryPolicy) -> Self {
+        ReliableDuplex {
+            duplex,
+            ack_timeout,
+            policy,
+            next: 0,
+            delivered: None,
+            pending: VecDeque::new(),
+            #[cfg(test)]
+            drop_ack_every: 0,
+            #[cfg(test)]
+            acks: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> Duplex {
+        self.duplex
+    }
+
+    /// send and block until the peer ACKs, `TimedOut` once the retry policy gives up
+    pub fn send_reliable(&mut self, payload: &[u8]) -> io::Result<()> {
+        let seqno = self.next;
+        let frame = encode(DATA, seqno, payload);
+        let policy = self.policy.clone();
+        policy.retry(
+            || {
+                self.duplex.send(&frame)?;
+                self.wait_ack(seqno)
+            },
+            |e| e.kind() == io::ErrorKind::TimedOut,
+        )?;
+        self.next = self.next.wrapping_add(1);
+        Ok(())
+    }
+
+    /// next message from the peer's `send_reliable`, ACKed and with retransmits removed, `Ok(None)` at EOF
+    pub fn recv_reliable(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if let Some(payload) = self.pending.pop_front() {
+            return Ok(Some(payload));
+        }
+        loop {
+            let frame = match self.duplex.read_message()? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+            if let Some(payload) = self.handle_data(&frame)? {
+                return Ok(Some(payload));
+            }
+        }
+    }
+
+    fn wait_ack(&mut self, seqno: u64) -> io::Result<()> {
+        let deadline = Instant::now() + self.ack_timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) || !self.duplex.reader_mut().wait_readable(Some(remaining))? {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("no ACK for seqno {} within {:?}", seqno, self.ack_timeout),
+                ));
+            }
+            let frame = match self.duplex.read_message()? {
+                Some(frame) => frame,
+                None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "peer closed before the ACK")),
+            };
+            let (kind, got, _) = decode(&frame)?;
+            if kind == ACK {
+                if got == seqno {
+                    return Ok(());
+                }
+                // late ACK of a message which was resent
+                continue;
+            }
+            if let Some(payload) = self.handle_data(&frame)? {
+                self.pending.push_back(payload);
+            }
+        }
+    }
+
+    /// ACK a data frame, its payload unless it was delivered before, stale ACKs are skipped
+    fn handle_data(&mut self, frame: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let (kind, seqno, payload) = decode(frame)?;
+        if kind == ACK {
+            return Ok(None);
+        }
+        if !self.ack_dropped() {
+            self.duplex.send(&encode(ACK, seqno, &[]))?;
+        }
+        match self.delivered {
+            Some(delivered) if seqno <= delivered => Ok(None),
+            _ => {
+                self.delivered = Some(seqno);
+                Ok(Some(payload.to_vec()))
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn ack_dropped(&mut self) -> bool {
+        self.acks += 1;
+        self.drop_ack_every != 0 && self.acks.is_multiple_of(self.drop_ack_every)
+    }
+
+    #[cfg(not(test))]
+    fn ack_dropped(&mut self) -> bool {
+        false
+    }
+}
+
+#[test]
+fn test_lost_acks_are_retransmitted() {
+    let (a_to_b, b_to_a, a, b) = ::core::duplex::test_duplex("reliable");
+    let policy = RetryPolicy {
+        max_attempts: 5,
+        initial_backoff: Duration::from_millis(1),
+        ..RetryPolicy::default()
+    };
+    let mut sender = ReliableDuplex::new(a, Duration::from_millis(50), policy.clone());
+    let mut receiver = ReliableDuplex::new(b, Duration::from_millis(50), policy);
+    receiver.drop_ack_every = 3;
+    let messages: Vec<Vec<u8>> = (0..20u8).map(|i| vec![i; i as usize + 1]).collect();
+    let producer = {
+        let messages = messages.clone();
+        std::thread::spawn(move || {
+            for message in &messages {
+                sender.send_reliable(message).unwrap();
+            }
+        })
+    };
+    let mut delivered = Vec::new();
+    while let Some(payload) = receiver.recv_reliable().unwrap() {
+        delivered.push(payload);
+    }
+    producer.join().unwrap();
+    assert_eq!(delivered, messages);
+    assert!(receiver.acks > messages.len() as u64, "no ACK was dropped and resent");
+    std::fs::remove_file(a_to_b.path()).unwrap();
+    std::fs::remove_file(b_to_a.path()).unwrap();
+}
@@ -0,0 +1,94 @@
+/*!
+frames behind a 4 byte sync marker, so a reader can find the next frame again after garbage in the stream
+
+`write_message_synced` puts the marker in front of an ordinary length-prefixed frame. `read_message_resync` expects
+the marker first: on anything else, or on a marker followed by a length above `MAX_MESSAGE_LEN`, it scans forward
+byte by byte to the next marker and reports how many bytes it skipped. A payload containing the marker itself is
+fine as long as the stream is in sync, it only matters while scanning
+*/
+use std::io::{self, IoSlice};
+
+use core::reader::PipeReader;
+use core::writer::PipeWriter;
+use framing::{encode_header, read_exact_or_eof, HEADER_LEN, MAX_MESSAGE_LEN};
+
+pub const SYNC_LEN: usize = 4;
+/// a marker for when the protocol doesn't pick its own, not valid UTF-8 so it's unlikely in text garbage
+pub const SYNC_MAGIC: [u8; SYNC_LEN] = [0xf1, 0xfe, 0x5e, 0xc5];
+
+impl PipeWriter {
+    /// `write_message` with `magic` in front, marker, header and payload go out in one writev
+    pub fn write_message_synced(&mut self, magic: [u8; SYNC_LEN], payload: &[u8]) -> io::Result<()> {
+        let header = encode_header(payload.len())?;
+        self.write_all_vectored(&mut [IoSlice::new(&magic), IoSlice::new(&header), IoSlice::new(payload)])
+    }
+}
+
+impl PipeReader {
+    /**
+    next frame of `write_message_synced` and the bytes skipped to find it, `(payload, 0)` while the stream is in sync
+
+    `Ok(None)` when the writer closed cleanly between frames, `UnexpectedEof` if it closed while scanning for a marker
+    or inside a frame
+    */
+    pub fn read_message_resync(&mut self, magic: [u8; SYNC_LEN]) -> io::Result<Option<(Vec<u8>, usize)>> {
+        let mut window = [0u8; SYNC_LEN];
+        if !read_exact_or_eof(self, &mut window)? {
+            return Ok(None);
+        }
+        let mut skipped = 0;
+        loop {
+            while window != magic {
+                let mut byte = [0u8; 1];
+                if !read_exact_or_eof(self, &mut byte)? {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("pipe closed after skipping {} bytes without a sync marker", skipped + SYNC_LEN),
+                    ));
+                }
+                window.rotate_left(1);
+                window[SYNC_LEN - 1] = byte[0];
+                skipped += 1;
+            }
+            let mut header = [0u8; HEADER_LEN];
+            if !read_exact_or_eof(self, &mut header)? {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "pipe closed after a sync marker"));
+            }
+            let len = u32::from_be_bytes(header) as usize;
+            if len > MAX_MESSAGE_LEN {
+                // a marker by chance inside garbage, the scan goes on from the bytes taken for its length
+                skipped += SYNC_LEN;
+                window = header;
+                continue;
+            }
+            let mut payload = vec![0u8; len];
+            if !read_exact_or_eof(self, &mut payload)? {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "pipe closed inside a message"));
+            }
+            return Ok(Some((payload, skipped)));
+        }
+    }
+}
+
+#[test]
+fn test_resync_skips_garbage() {
+    use std::io::Write;
+
+    let (pipe, mut reader, mut writer) = ::test_pair("resync");
+    writer.write_message_synced(SYNC_MAGIC, b"first").unwrap();
+    // raw bytes of a non-framing producer, including a stray marker followed by an impossible length
+    let mut garbage = b"junk".to_vec();
+    garbage.extend_from_slice(&SYNC_MAGIC);
+    garbage.extend_from_slice(&[0xff; HEADER_LEN]);
+    garbage.push(b'x');
+    writer.write_all(&garbage).unwrap();
+    writer.write_message_synced(SYNC_MAGIC, b"second").unwrap();
+    writer.write_message_synced(SYNC_MAGIC, b"third").unwrap();
+    drop(writer);
+
+    assert_eq!(reader.read_message_resync(SYNC_MAGIC).unwrap(), Some((b"first".to_vec(), 0)));
+    assert_eq!(reader.read_message_resync(SYNC_MAGIC).unwrap(), Some((b"second".to_vec(), garbage.len())));
+    assert_eq!(reader.read_message_resync(SYNC_MAGIC).unwrap(), Some((b"third".to_vec(), 0)));
+    assert_eq!(reader.read_message_resync(SYNC_MAGIC).unwrap(), None);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
@@ -0,0 +1,157 @@
+/*!
+frames carrying a sequence number, so a consumer notices dropped messages
+
+the seqno is the first 8 bytes (big-endian) of an ordinary length-prefixed frame, so to a plain `read_message` it's just part of the payload
+*/
+use std::io::{self, IoSlice};
+
+use core::error::PipeError;
+use core::reader::PipeReader;
+use core::writer::PipeWriter;
+use framing::encode_header;
+
+pub const SEQNO_LEN: usize = 8;
+
+/// stamps every message with the next seqno, starting at 0
+#[derive(Debug)]
+pub struct SequencedWriter {
+    writer: PipeWriter,
+    next: u64,
+}
+
+impl SequencedWriter {
+    pub fn new(writer: PipeWriter) -> Self {
+        SequencedWriter { writer, next: 0 }
+    }
+
+    /// seqno the next `write_message` will use
+    pub fn next_seqno(&self) -> u64 {
+        self.next
+    }
+
+    pub fn write_message(&mut self, payload: &[u8]) -> io::Result<u64> {
+        let seqno = self.next;
+        let header = encode_header(SEQNO_LEN + payload.len())?;
+        let seqno_bytes = seqno.to_be_bytes();
+        // header, seqno and payload in one writev, no concatenated copy
+        self.writer
+            .write_all_vectored(&mut [IoSlice::new(&header), IoSlice::new(&seqno_bytes), IoSlice::new(payload)])?;
+        self.next = self.next.wrapping_add(1);
+        Ok(seqno)
+    }
+
+    pub fn get_mut(&mut self) -> &mut PipeWriter {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> PipeWriter {
+        self.writer
+    }
+}
+
+/**
+reads seqno frames and checks they are consecutive
+
+the first frame sets the expectation. On a gap `read_message` fails once with `PipeError::MissedMessages`,
+the frame which revealed the gap is kept and returned by the next call, so nothing more gets lost
+*/
+#[derive(Debug)]
+pub struct SequencedReader {
+    reader: PipeReader,
+    expected: Option<u64>,
+    pending: Option<(u64, Vec<u8>)>,
+}
+
+impl SequencedReader {
+    pub fn new(reader: PipeReader) -> Self {
+        SequencedReader {
+            reader,
+            expected: None,
+            pending: None,
+        }
+    }
+
+    /// seqno the next frame should carry, `None` before the first frame
+    pub fn expected_seqno(&self) -> Option<u64> {
+        self.expected
+    }
+
+    pub fn read_message(&mut self) -> io::Result<Option<(u64, Vec<u8>)>> {
+        if let Some(frame) = self.pending.take() {
+            return Ok(Some(frame));
+        }
+        let body = match self.reader.read_message()? {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+        if body.len() < SEQNO_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame of {} bytes is too short for a seqno", body.len()),
+            ));
+        }
+        let mut seqno = [0u8; SEQNO_LEN];
+        seqno.copy_from_slice(&body[..SEQNO_LEN]);
+        let got = u64::from_be_bytes(seqno);
+        let payload = body[SEQNO_LEN..].to_vec();
+        let expected = self.expected.replace(got.wrapping_add(1));
+        match expected {
+            Some(expected) if expected != got => {
+                self.pending = Some((got, payload));
+                Err(PipeError::MissedMessages { expected, got }.into())
+            }
+            _ => Ok(Some((got, payload))),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut PipeReader {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> PipeReader {
+        self.reader
+    }
+}
+
+#[test]
+fn test_seqno_gap_detection() {
+    let (pipe, reader, writer) = ::test_pair("seqno_gap");
+    let mut writer = SequencedWriter::new(writer);
+    for payload in &[b"zero", b"one_", b"two_"] {
+        writer.write_message(&payload[..]).unwrap();
+    }
+    drop(writer);
+
+    let mut reader = SequencedReader::new(reader);
+    assert_eq!(reader.read_message().unwrap().unwrap(), (0, b"zero".to_vec()));
+    // drain the middle frame behind the sequenced reader's back
+    reader.get_mut().read_message().unwrap().unwrap();
+    let err = reader.read_message().unwrap_err();
+    match PipeError::downcast(&err) {
+        Some(&PipeError::MissedMessages { expected: 1, got: 2 }) => {}
+        other => panic!("unexpected error {:?}", other),
+    }
+    assert_eq!(reader.read_message().unwrap().unwrap(), (2, b"two_".to_vec()));
+    assert_eq!(reader.expected_seqno(), Some(3));
+    assert_eq!(reader.read_message().unwrap(), None);
+    std::fs::remove_file(pipe.path()).unwrap();
+}
+
+#[test]
+fn test_golden_seqno_wire_format() {
+    use std::io::Read;
+
+    let (pipe, mut reader, writer) = ::test_pair("golden_seqno_wire");
+    let mut writer = SequencedWriter::new(writer);
+    writer.write_message(b"").unwrap();
+    assert_eq!(writer.write_message(b"hi").unwrap(), 1);
+    drop(writer);
+    let mut wire = Vec::new();
+    reader.read_to_end(&mut wire).unwrap();
+    #[rustfmt::skip]
+    assert_eq!(wire, [
+        0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x68, 0x69,
+    ]);
+    std::fs::remove_file(pipe.path()).unwrap();
+}